@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use http::{Request, StatusCode};
+use http::date::DateTime;
+use http::json::JsonValue;
+
+/// The line format of an access log.
+/// `Common` is the Common Log Format:
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`.
+/// `Combined` adds the quoted referer and user agent, followed by the time
+/// taken to answer in microseconds and the quoted request ID.
+/// `Json` writes one JSON object per line with all of the same fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+/// Where an access log is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Stdout,
+    File(String),
+}
+
+/// Records a line for every request the server answers. Set one on the
+/// Server with `Server::with_access_log`. Logs written to a file can be
+/// rotated once they reach a size: the full log is renamed to `<path>.1`,
+/// older logs move up to `<path>.2` and so on, and only `max_files` old
+/// logs are kept.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    target: LogTarget,
+    max_size: Option<u64>,
+    max_files: usize,
+    file: Arc<Mutex<Option<LogFile>>>,
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: &str) -> io::Result<LogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { file, size })
+    }
+}
+
+impl AccessLog {
+    /// Creates an access log that prints Common Log Format lines to stdout.
+    pub fn stdout() -> AccessLog {
+        AccessLog {
+            format: LogFormat::Common,
+            target: LogTarget::Stdout,
+            max_size: None,
+            max_files: 5,
+            file: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates an access log that appends Common Log Format lines to a file.
+    pub fn file(path: &str) -> AccessLog {
+        AccessLog {
+            target: LogTarget::File(String::from(path)),
+            ..AccessLog::stdout()
+        }
+    }
+
+    pub fn with_format(mut self, format: LogFormat) -> AccessLog {
+        self.format = format;
+        self
+    }
+
+    /// Rotates the log file before it grows past `bytes`.
+    pub fn with_max_size(mut self, bytes: u64) -> AccessLog {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Sets how many rotated log files are kept. The default is 5.
+    pub fn with_max_files(mut self, count: usize) -> AccessLog {
+        self.max_files = count;
+        self
+    }
+
+    pub fn get_format(&self) -> LogFormat {
+        self.format
+    }
+
+    pub fn get_target(&self) -> LogTarget {
+        self.target.clone()
+    }
+
+    /// Writes an entry to the log.
+    pub fn record(&self, entry: &AccessLogEntry) -> io::Result<()> {
+        let line = entry.format(self.format);
+        match self.target {
+            LogTarget::Stdout => {
+                let stdout = io::stdout();
+                let mut handle = stdout.lock();
+                writeln!(handle, "{}", line)
+            },
+            LogTarget::File(ref path) => self.write_to_file(path, &line),
+        }
+    }
+
+    fn write_to_file(&self, path: &str, line: &str) -> io::Result<()> {
+        let mut guard = match self.file.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let length = line.len() as u64 + 1;
+        if guard.is_none() {
+            *guard = Some(LogFile::open(path)?);
+        }
+        let full = match (guard.as_ref(), self.max_size) {
+            (Some(log), Some(max_size)) => log.size > 0 && log.size + length > max_size,
+            _ => false,
+        };
+        if full {
+            *guard = None;
+            self.rotate(path)?;
+            *guard = Some(LogFile::open(path)?);
+        }
+        let log = guard.as_mut().unwrap();
+        writeln!(log.file, "{}", line)?;
+        log.size += length;
+        Ok(())
+    }
+
+    /// Moves the current log to `<path>.1`, shifting older logs up by one
+    /// and removing the oldest.
+    fn rotate(&self, path: &str) -> io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(path);
+        }
+        let _ = fs::remove_file(format!("{}.{}", path, self.max_files));
+        for index in (1..self.max_files).rev() {
+            let _ = fs::rename(format!("{}.{}", path, index), format!("{}.{}", path, index + 1));
+        }
+        fs::rename(path, format!("{}.1", path))
+    }
+}
+
+/// Everything recorded about a single request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogEntry {
+    client_ip: String,
+    timestamp: DateTime,
+    request_line: String,
+    status: StatusCode,
+    bytes: usize,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    latency: Duration,
+    request_id: Option<String>,
+}
+
+impl AccessLogEntry {
+    /// Starts an entry for a request from the given client, timestamped now.
+    /// The request line is taken from the raw request so that requests Servo
+    /// couldn't parse are still logged as they were sent.
+    pub fn new(client_ip: &str, raw_request: &[u8]) -> AccessLogEntry {
+        let head = String::from_utf8_lossy(raw_request);
+        let request_line = head.trim_start().lines().next().unwrap_or("").trim();
+        AccessLogEntry {
+            client_ip: String::from(client_ip),
+            timestamp: DateTime::now(),
+            request_line: String::from(request_line),
+            status: 0,
+            bytes: 0,
+            referer: None,
+            user_agent: None,
+            latency: Duration::from_secs(0),
+            request_id: None,
+        }
+    }
+
+    /// Fills in the referer, user agent and request ID from a parsed Request.
+    pub fn with_request(mut self, request: &Request) -> AccessLogEntry {
+        self.referer = request.get_header("Referer");
+        self.user_agent = request.get_header("User-Agent");
+        let request_id = request.get_request_id();
+        self.request_id = if request_id.is_empty() { None } else { Some(request_id) };
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: DateTime) -> AccessLogEntry {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> AccessLogEntry {
+        self.status = status;
+        self
+    }
+
+    /// Sets the number of body bytes sent.
+    pub fn with_bytes(mut self, bytes: usize) -> AccessLogEntry {
+        self.bytes = bytes;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> AccessLogEntry {
+        self.latency = latency;
+        self
+    }
+
+    pub fn get_client_ip(&self) -> String {
+        self.client_ip.clone()
+    }
+
+    pub fn get_request_line(&self) -> String {
+        self.request_line.clone()
+    }
+
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn get_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn get_latency(&self) -> Duration {
+        self.latency
+    }
+
+    pub fn get_request_id(&self) -> Option<String> {
+        self.request_id.clone()
+    }
+
+    /// Formats the entry as a single log line, without the line ending.
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.common(),
+            LogFormat::Combined => format!("{} \"{}\" \"{}\" {} \"{}\"",
+                                           self.common(),
+                                           quoted(self.referer.as_ref()),
+                                           quoted(self.user_agent.as_ref()),
+                                           self.latency_micros(),
+                                           quoted(self.request_id.as_ref())),
+            LogFormat::Json => self.json().stringify(),
+        }
+    }
+
+    fn common(&self) -> String {
+        let bytes = if self.bytes == 0 { String::from("-") } else { self.bytes.to_string() };
+        format!("{} - - [{}] \"{}\" {} {}",
+                self.client_ip,
+                self.timestamp.to_clf(),
+                escape(&self.request_line),
+                self.status,
+                bytes)
+    }
+
+    fn json(&self) -> JsonValue {
+        let mut object = BTreeMap::new();
+        object.insert(String::from("client_ip"), JsonValue::from(self.client_ip.clone()));
+        object.insert(String::from("timestamp"), JsonValue::from(self.timestamp.to_iso8601()));
+        object.insert(String::from("request"), JsonValue::from(self.request_line.clone()));
+        object.insert(String::from("status"), JsonValue::from(self.status));
+        object.insert(String::from("bytes"), JsonValue::from(self.bytes as f64));
+        object.insert(String::from("referer"), JsonValue::from(self.referer.clone()));
+        object.insert(String::from("user_agent"), JsonValue::from(self.user_agent.clone()));
+        object.insert(String::from("request_id"), JsonValue::from(self.request_id.clone()));
+        object.insert(String::from("latency_ms"), JsonValue::from(self.latency_micros() as f64 / 1000.0));
+        JsonValue::Object(object)
+    }
+
+    fn latency_micros(&self) -> u64 {
+        self.latency.as_secs() * 1_000_000 + u64::from(self.latency.subsec_micros())
+    }
+}
+
+/// Returns a header value for a quoted log field, or `-` if it is missing.
+fn quoted(value: Option<&String>) -> String {
+    match value {
+        Some(value) => escape(value),
+        None => String::from("-"),
+    }
+}
+
+/// Escapes quotes, backslashes and control characters so a client can't
+/// break up or forge log lines.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_access_log_formats() {
+    let entry = AccessLogEntry::new("10.0.0.1", b"GET /a\"b HTTP/1.1\r\nHost: x\r\n\r\n")
+        .with_request(&Request::from("GET / HTTP/1.1\r\nUser-Agent: curl/8.0\r\n\r\n"))
+        .with_timestamp(DateTime::from_unix(971_186_136))
+        .with_status(200)
+        .with_bytes(2326)
+        .with_latency(Duration::from_millis(15));
+    assert_eq!(entry.format(LogFormat::Common),
+               "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326");
+    assert_eq!(entry.format(LogFormat::Combined),
+               "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2326 \"-\" \"curl/8.0\" 15000 \"-\"");
+    let json = JsonValue::parse(&entry.format(LogFormat::Json)).unwrap();
+    assert_eq!(json.get("timestamp").and_then(|t| t.as_str()), Some("2000-10-10T13:55:36Z"));
+    assert_eq!(json.get("latency_ms").and_then(|l| l.as_f64()), Some(15.0));
+    assert!(json.get("referer").unwrap().is_null());
+    let empty = entry.with_bytes(0).with_status(304);
+    assert!(empty.format(LogFormat::Common).ends_with("\" 304 -"));
+}
+
+#[test]
+fn test_access_log_file_rotation() {
+    let dir = ::std::env::temp_dir().join("servo_test_access_log_rotation");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = format!("{}/access.log", dir.display());
+    let entry = AccessLogEntry::new("127.0.0.1", b"GET / HTTP/1.1").with_status(200).with_bytes(9);
+    let line_length = entry.format(LogFormat::Common).len() as u64 + 1;
+    // Room for two lines per file, so seven lines leave one in the current
+    // log, two in each of the two kept logs and drop the oldest two.
+    let log = AccessLog::file(&path).with_max_size(line_length * 2 + 1).with_max_files(2);
+    for _ in 0..7 {
+        log.clone().record(&entry).unwrap();
+    }
+    assert_eq!(fs::metadata(&path).unwrap().len(), line_length);
+    assert_eq!(fs::metadata(format!("{}.1", path)).unwrap().len(), line_length * 2);
+    assert_eq!(fs::metadata(format!("{}.2", path)).unwrap().len(), line_length * 2);
+    assert!(fs::metadata(format!("{}.3", path)).is_err());
+}
+
+#[test]
+fn test_handle_request_writes_access_log() {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use super::{Configuration, Server, handle_request};
+    let dir = ::std::env::temp_dir().join("servo_test_access_log_server");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = format!("{}/access.log", dir.display());
+    let configs = Configuration::new().with_server_configurations(
+        Server::new().with_access_log(AccessLog::file(&path).with_format(LogFormat::Combined)));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nReferer: http://example.com/\r\nX-Request-Id: abc-123\r\n\r\n").unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    handle_request(stream, &configs, configs.metrics.track_connection());
+    let mut reply = Vec::new();
+    client.read_to_end(&mut reply).unwrap();
+    let logged = fs::read_to_string(&path).unwrap();
+    assert!(logged.starts_with("127.0.0.1 - - ["));
+    assert!(logged.contains("] \"GET / HTTP/1.1\" 200 9 \"http://example.com/\" \"-\" "));
+    assert!(logged.ends_with(" \"abc-123\"\n"));
+    assert!(String::from_utf8_lossy(&reply).contains("X-Request-Id: abc-123"));
+}
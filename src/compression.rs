@@ -0,0 +1,467 @@
+use super::Configuration;
+use super::middleware::Middleware;
+use http::{Request, Response};
+use http::content_type::ContentType;
+
+/// Responses smaller than this many bytes aren't compressed by default, as
+/// the savings wouldn't make up for the work.
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Middleware compressing response bodies with gzip or deflate when the
+/// client's `Accept-Encoding` allows it, preferring gzip when both are
+/// equally acceptable. Bodies smaller than the minimum size, streaming
+/// bodies, responses that already have a `Content-Encoding` and content
+/// types that are compressed already, such as PNG and JPEG images, are
+/// sent as they are. Compressed responses get `Vary: Accept-Encoding`, and
+/// their ETag is made weak since the bytes no longer match the original.
+/// Add it with `Configuration::with_middleware(Compression::new())`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the smallest body, in bytes, that will be compressed.
+    pub fn with_min_size(mut self, min_size: usize) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn get_min_size(&self) -> usize {
+        self.min_size
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, request: &Request, mut response: Response, _configs: &Configuration) -> Response {
+        let headers = response.get_headers().unwrap_or_default();
+        let header = |name: &str| headers.iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone());
+        let content_type = header("Content-Type").unwrap_or_else(|| response.get_content_type().stringify());
+        if response.get_status() != 200
+            || response.is_streaming()
+            || response.get_body().len() < self.min_size
+            || header("Content-Encoding").is_some()
+            || !is_compressible(response.get_content_type(), &content_type) {
+            return response;
+        }
+        let vary = match header("Vary") {
+            Some(ref vary) if vary.to_lowercase().contains("accept-encoding") => vary.clone(),
+            Some(vary) => format!("{}, Accept-Encoding", vary),
+            None => String::from("Accept-Encoding"),
+        };
+        response = replace_header(response, "Vary", &vary);
+        let gzip_quality = request.encoding_quality("gzip");
+        let deflate_quality = request.encoding_quality("deflate");
+        let (coding, body) = if gzip_quality > 0.0 && gzip_quality >= deflate_quality {
+            ("gzip", gzip(&response.get_body()))
+        } else if deflate_quality > 0.0 {
+            ("deflate", zlib(&response.get_body()))
+        } else {
+            return response;
+        };
+        response.add_header("Content-Encoding", coding);
+        if let Some(etag) = header("ETag") {
+            if !etag.starts_with("W/") {
+                response = replace_header(response, "ETag", &format!("W/{}", etag));
+            }
+        }
+        response.with_body(body)
+    }
+}
+
+/// Sets a header on the response, dropping any header of the same name
+/// that was set with different case so only one is sent.
+fn replace_header(response: Response, name: &str, value: &str) -> Response {
+    let mut headers = response.get_headers().unwrap_or_default();
+    headers.retain(|key, _| !key.eq_ignore_ascii_case(name));
+    headers.insert(String::from(name), String::from(value));
+    response.with_headers(headers)
+}
+
+/// Returns false for content types whose data is compressed already, where
+/// compressing again would only waste time.
+fn is_compressible(content_type: ContentType, header: &str) -> bool {
+    match content_type {
+        ContentType::ImagePng | ContentType::ImageJpg | ContentType::ImageGif | ContentType::MultipartByteranges => false,
+        _ => {
+            let media_type = header.split(';').next().unwrap_or("").trim().to_lowercase();
+            let compressed = ["application/zip", "application/gzip", "application/x-gzip", "application/octet-stream",
+                              "application/pdf", "image/webp", "image/avif", "font/woff", "font/woff2"];
+            !(media_type.starts_with("image/") && !media_type.ends_with("+xml") && media_type != "image/bmp"
+                || media_type.starts_with("video/")
+                || media_type.starts_with("audio/")
+                || compressed.contains(&media_type.as_str()))
+        },
+    }
+}
+
+/// Compresses data in the gzip format (RFC 1952), as sent with
+/// `Content-Encoding: gzip`.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic number, DEFLATE, no flags, no modification time, no extra
+    // flags, unknown operating system.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Compresses data in the zlib format (RFC 1950), which is what
+/// `Content-Encoding: deflate` means despite its name.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+const WINDOW_SIZE: usize = 32_768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+const LENGTH_BASE: [usize; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                  35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [usize; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                    8193, 12_289, 16_385, 24_577];
+const DISTANCE_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                   7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Compresses data into a raw DEFLATE stream (RFC 1951). Repeats are found
+/// with LZ77 over a 32 KiB window and written as a single block using the
+/// fixed Huffman codes, which does well on the text Servo usually sends.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // BFINAL, then BTYPE 01 for the fixed Huffman codes.
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    let mut matcher = Matcher::new();
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = matcher.longest_match(data, position);
+        matcher.insert(data, position);
+        if length >= MIN_MATCH {
+            writer.write_length(length);
+            writer.write_distance(distance);
+            for skipped in position + 1..position + length {
+                matcher.insert(data, skipped);
+            }
+            position += length;
+        } else {
+            writer.write_literal_length(u32::from(data[position]));
+            position += 1;
+        }
+    }
+    writer.write_literal_length(256);
+    writer.finish()
+}
+
+/// Finds earlier occurrences of the bytes at a position through hash
+/// chains: `head` holds the latest position whose next three bytes have a
+/// given hash, and `previous` links each position to the one before it with
+/// the same hash.
+struct Matcher {
+    head: Vec<usize>,
+    previous: Vec<usize>,
+}
+
+impl Matcher {
+    fn new() -> Matcher {
+        Matcher {
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            previous: vec![usize::MAX; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(data: &[u8], position: usize) -> usize {
+        let bytes = (usize::from(data[position]) << 16) | (usize::from(data[position + 1]) << 8) | usize::from(data[position + 2]);
+        (bytes.wrapping_mul(2_654_435_761) >> 8) & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let hash = Matcher::hash(data, position);
+            self.previous[position % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// Returns the length and distance of the longest earlier match for the
+    /// bytes at `position`, or a length of 0 if there is none.
+    fn longest_match(&self, data: &[u8], position: usize) -> (usize, usize) {
+        if position + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_length = MAX_MATCH.min(data.len() - position);
+        let mut best = (0, 0);
+        let mut candidate = self.head[Matcher::hash(data, position)];
+        let mut chain = 0;
+        while candidate < position && position - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let length = data[candidate..].iter()
+                .zip(&data[position..position + max_length])
+                .take_while(|&(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, position - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate % WINDOW_SIZE];
+            chain += 1;
+        }
+        best
+    }
+}
+
+/// Writes a DEFLATE bit stream, which packs values starting from the least
+/// significant bit of each byte but Huffman codes starting from their most
+/// significant bit.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Writes a literal byte, the end of block marker (256) or a length
+    /// code (257 to 285) with its fixed Huffman code.
+    fn write_literal_length(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base <= length).unwrap_or(0);
+        self.write_literal_length(257 + index as u32);
+        self.write_bits((length - LENGTH_BASE[index]) as u32, LENGTH_EXTRA[index]);
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE.iter().rposition(|&base| base <= distance).unwrap_or(0);
+        self.write_code(index as u32, 5);
+        self.write_bits((distance - DISTANCE_BASE[index]) as u32, DISTANCE_EXTRA[index]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// The CRC-32 checksum gzip ends with.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        let mut value = index as u32;
+        for _ in 0..8 {
+            value = if value & 1 == 1 { 0xedb8_8320 ^ (value >> 1) } else { value >> 1 };
+        }
+        *entry = value;
+    }
+    !data.iter().fold(!0u32, |crc, &byte| table[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// The Adler-32 checksum zlib ends with.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65_521;
+        b %= 65_521;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a stream written by `deflate`, which only uses fixed
+/// Huffman blocks, so the tests can check what was written.
+#[cfg(test)]
+fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut bit = 0;
+    let mut read_bits = |count: u32| -> usize {
+        let mut value = 0;
+        for shift in 0..count {
+            value |= usize::from((data[bit / 8] >> (bit % 8)) & 1) << shift;
+            bit += 1;
+        }
+        value
+    };
+    let mut out: Vec<u8> = Vec::new();
+    assert_eq!(read_bits(3), 0b011);
+    loop {
+        let mut code = 0;
+        for _ in 0..7 {
+            code = (code << 1) | read_bits(1);
+        }
+        let symbol = if code <= 0x17 {
+            code + 256
+        } else {
+            code = (code << 1) | read_bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => code - 0xc0 + 280,
+                _ => ((code << 1) | read_bits(1)) - 0x190 + 144,
+            }
+        };
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return out,
+            _ => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] + read_bits(LENGTH_EXTRA[index]);
+                let mut code = 0;
+                for _ in 0..5 {
+                    code = (code << 1) | read_bits(1);
+                }
+                let distance = DISTANCE_BASE[code] + read_bits(DISTANCE_EXTRA[code]);
+                for _ in 0..length {
+                    let byte = out[out.len() - distance];
+                    out.push(byte);
+                }
+            },
+        }
+    }
+}
+
+#[test]
+fn test_deflate_round_trip() {
+    assert_eq!(deflate(b""), vec![0x03, 0x00]);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    let mut samples: Vec<Vec<u8>> = vec![
+        b"a".to_vec(),
+        b"abcabcabcabcabcabcabc".to_vec(),
+        vec![0; 100_000],
+        (0..=255u8).cycle().take(70_000).collect(),
+    ];
+    let json: String = (0..2000).map(|id| format!("{{\"id\":{},\"name\":\"user {}\",\"active\":true}},", id, id % 7)).collect();
+    samples.push(json.into_bytes());
+    for sample in samples {
+        let compressed = deflate(&sample);
+        assert_eq!(inflate_fixed(&compressed), sample);
+        if sample.len() > 1000 {
+            assert!(compressed.len() < sample.len() / 3, "{} of {}", compressed.len(), sample.len());
+        }
+    }
+    let gzipped = gzip(b"hello hello hello");
+    assert_eq!(&gzipped[..3], &[0x1f, 0x8b, 8]);
+    assert_eq!(inflate_fixed(&gzipped[10..gzipped.len() - 8]), b"hello hello hello".to_vec());
+    assert_eq!(&gzipped[gzipped.len() - 4..], &[17, 0, 0, 0]);
+    let zlibbed = zlib(b"hello hello hello");
+    assert_eq!((u32::from(zlibbed[0]) * 256 + u32::from(zlibbed[1])) % 31, 0);
+    assert_eq!(&zlibbed[zlibbed.len() - 4..], &adler32(b"hello hello hello").to_be_bytes());
+}
+
+#[test]
+fn test_compression_middleware() {
+    use super::{Routes, route_request};
+    use http;
+    let page = "<p>hello</p>".repeat(200);
+    let png = vec![0u8; 4096];
+    let page_body = page.clone();
+    let configs = Configuration::new()
+        .with_routes(Routes::new()
+            .with_route("GET /page", move |_: Request, _: &Configuration| {
+                http::ok(page_body.clone(), ContentType::TextHtml).with_header("ETag", "\"v1\"")
+            })
+            .with_route("GET /small", |_: Request, _: &Configuration| http::ok(String::from("tiny"), ContentType::TextHtml))
+            .with_route("GET /logo.png", move |_: Request, _: &Configuration| http::ok_file(png.clone(), ContentType::ImagePng)))
+        .with_middleware(Compression::new().with_min_size(100));
+    let get = |route: &str, accept: &str| route_request(
+        Request::from(format!("GET {} HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", route, accept).as_str()), &configs);
+    let header = |resp: &Response, name: &str| resp.get_headers().and_then(|headers| headers.get(name).cloned());
+
+    let resp = get("/page", "gzip, deflate");
+    assert_eq!(header(&resp, "Content-Encoding"), Some(String::from("gzip")));
+    assert_eq!(header(&resp, "Vary"), Some(String::from("Accept-Encoding")));
+    assert_eq!(header(&resp, "ETag"), Some(String::from("W/\"v1\"")));
+    let body = resp.get_body();
+    assert!(body.len() < page.len() / 10);
+    assert_eq!(inflate_fixed(&body[10..body.len() - 8]), page.as_bytes().to_vec());
+
+    let resp = get("/page", "gzip;q=0.5, deflate");
+    assert_eq!(header(&resp, "Content-Encoding"), Some(String::from("deflate")));
+    let body = resp.get_body();
+    assert_eq!(inflate_fixed(&body[2..body.len() - 4]), page.as_bytes().to_vec());
+
+    let resp = get("/page", "br");
+    assert_eq!((header(&resp, "Content-Encoding"), header(&resp, "Vary")), (None, Some(String::from("Accept-Encoding"))));
+    assert_eq!(resp.get_body(), page.as_bytes().to_vec());
+    assert_eq!(get("/small", "gzip").get_body(), b"tiny".to_vec());
+    assert_eq!(header(&get("/logo.png", "gzip"), "Content-Encoding"), None);
+    assert!(!is_compressible(ContentType::TextHtml, "video/mp4"));
+    assert!(is_compressible(ContentType::TextHtml, "image/svg+xml"));
+}
+
+#[test]
+fn test_compression_lowercase_headers() {
+    use super::{Routes, route_request};
+    use http;
+    let configs = Configuration::new()
+        .with_routes(Routes::new()
+            .with_route("GET /page", |_: Request, _: &Configuration| {
+                http::ok("<p>hello</p>".repeat(200), ContentType::TextHtml)
+                    .with_header("etag", "\"v1\"")
+                    .with_header("vary", "Cookie")
+            }))
+        .with_middleware(Compression::new());
+    let resp = route_request(Request::from("GET /page HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n"), &configs);
+    let headers = resp.get_headers().unwrap();
+    let named = |name: &str| headers.iter()
+        .filter(|&(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+        .collect::<Vec<String>>();
+    assert_eq!(named("ETag"), vec![String::from("W/\"v1\"")]);
+    assert_eq!(named("Vary"), vec![String::from("Cookie, Accept-Encoding")]);
+    assert_eq!(named("Content-Encoding"), vec![String::from("gzip")]);
+}
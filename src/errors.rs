@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use super::{Configuration, get_html};
+use http::{self, HttpError, IntoResponse, Request, Response};
+use http::content_type::ContentType;
+use http::json::JsonValue;
+
+/// Builds the Response for a request that ended in an error, such as a
+/// route that doesn't exist or a handler that panicked. Error handlers are
+/// registered on the Configuration per status code. This is implemented
+/// for every `Fn(&HttpError, &Request, &Configuration) -> R` that is
+/// `Send + Sync` and returns something implementing `http::IntoResponse`,
+/// and for `ErrorPage`.
+pub trait ErrorHandler: Send + Sync {
+    fn call(&self, error: &HttpError, request: &Request, configs: &Configuration) -> Response;
+}
+
+impl<F, R> ErrorHandler for F
+    where F: Fn(&HttpError, &Request, &Configuration) -> R + Send + Sync, R: IntoResponse {
+    fn call(&self, error: &HttpError, request: &Request, configs: &Configuration) -> Response {
+        self(error, request, configs).into_response()
+    }
+}
+
+/// An error page rendered from a template in the HTML directory. Any
+/// `{{status}}` in the template is replaced with the status code and any
+/// `{{message}}` with the error message. Clients that prefer JSON are sent
+/// the same JSON body as the default error response instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPage {
+    template: String,
+}
+
+impl ErrorPage {
+    pub fn new(template: &str) -> ErrorPage {
+        ErrorPage {
+            template: String::from(template),
+        }
+    }
+
+    pub fn get_template(&self) -> String {
+        self.template.clone()
+    }
+}
+
+impl ErrorHandler for ErrorPage {
+    fn call(&self, error: &HttpError, request: &Request, configs: &Configuration) -> Response {
+        if request.prefers_json() {
+            return default_error_response(error, request);
+        }
+        let page = get_html(&self.template, configs);
+        if page.is_empty() {
+            return default_error_response(error, request);
+        }
+        let page = page
+            .replace("{{status}}", &error.get_status().to_string())
+            .replace("{{message}}", &http::escape_html(&error.get_message()));
+        http::ok(page, ContentType::TextHtml).with_status(error.get_status())
+    }
+}
+
+/// The Response sent for an error when no handler is registered for its
+/// status. Clients that prefer JSON get `{"error": message, "status": code}`
+/// and everyone else gets the message as the page body.
+pub fn default_error_response(error: &HttpError, request: &Request) -> Response {
+    if request.prefers_json() {
+        let mut body = BTreeMap::new();
+        body.insert(String::from("error"), JsonValue::from(error.get_message()));
+        body.insert(String::from("status"), JsonValue::from(error.get_status()));
+        JsonValue::Object(body).into_response().with_status(error.get_status())
+    } else {
+        http::ok(error.get_message(), ContentType::TextHtml).with_status(error.get_status())
+    }
+}
+
+#[test]
+fn test_default_error_responses() {
+    use super::{Routes, route_request};
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_route("POST /users", |_, _| "created"));
+    let json = (String::from("Accept"), String::from("application/json, text/html;q=0.9"));
+    let resp = route_request(Request::new().with_method(String::from("GET")).with_route(String::from("/missing")), &configs);
+    assert_eq!(resp, http::not_found(String::from("Route not found."), ContentType::TextHtml));
+    let resp = route_request(Request::new().with_method(String::from("GET")).with_route(String::from("/missing")).with_header(json.clone()), &configs);
+    assert_eq!(resp.get_status(), 404);
+    assert_eq!(resp.get_body(), b"{\"error\":\"Route not found.\",\"status\":404}".to_vec());
+    let resp = route_request(Request::new().with_method(String::from("DELETE")).with_route(String::from("/users")), &configs);
+    assert_eq!(resp.get_status(), 405);
+    assert_eq!(resp.get_headers().unwrap()["Allow"], "POST");
+    let resp = route_request(Request::new().with_method(String::from("GET")).with_route(String::from("/static/nothing.css")), &configs);
+    assert_eq!(resp, http::not_found(String::from("Could not find resource"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_error_handlers_and_pages() {
+    use super::{Routes, Server, route_request};
+    let dir = ::std::env::temp_dir().join("servo_test_error_pages");
+    ::std::fs::create_dir_all(&dir).unwrap();
+    ::std::fs::write(dir.join("404.html"), "<h1>{{status}}</h1><p>{{message}}</p>").unwrap();
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_html_dir(&format!("{}/", dir.display())))
+        .with_routes(Routes::new().with_route("GET /boom", |_: Request, _: &Configuration| -> Response { panic!("boom") }))
+        .with_error_page(404, "404.html")
+        .with_error_handler(500, |e, req, _| format!("{} for {}", e.get_status(), req.get_path()));
+    let resp = route_request(Request::new().with_method(String::from("GET")).with_route(String::from("/a<b>")), &configs);
+    assert_eq!(resp, http::not_found(String::from("<h1>404</h1><p>Route not found.</p>"), ContentType::TextHtml));
+    let resp = route_request(Request::new().with_method(String::from("GET")).with_route(String::from("/boom")), &configs);
+    assert_eq!(resp, http::ok(String::from("500 for /boom"), ContentType::TextHtml).with_status(500));
+    let page = ErrorPage::new("missing.html");
+    let error = HttpError::new(404, "<gone>");
+    let resp = page.call(&error, &Request::new(), &configs);
+    assert_eq!(resp, http::not_found(String::from("<gone>"), ContentType::TextHtml));
+}
@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::{Configuration, Handler};
+use http::{self, HttpError, IntoResponse, Request, Response};
+use http::json::{FromJson, JsonValue};
+
+/// Types that can be pulled out of a Request before it reaches a handler.
+/// Handlers whose arguments all implement FromRequest can be registered by
+/// wrapping them with `extract`. If any argument can't be built, its error
+/// is sent back instead of calling the handler: a 400 for anything the
+/// client got wrong and a 500 for missing application state.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request, configs: &Configuration) -> Result<Self, HttpError>;
+}
+
+/// The whole Request, for handlers that still want to dig through it.
+impl FromRequest for Request {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Request, HttpError> {
+        Ok(request.clone())
+    }
+}
+
+/// An optional extractor never fails. It is None when the inner extractor
+/// would have.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &Request, configs: &Configuration) -> Result<Option<T>, HttpError> {
+        Ok(T::from_request(request, configs).ok())
+    }
+}
+
+/// Types that can be built from a route's URL arguments. A single value is
+/// parsed from all of the arguments joined with `/`, and tuples take one
+/// argument each, in order.
+pub trait FromUrlArgs: Sized {
+    fn from_url_args(args: &[String]) -> Result<Self, String>;
+}
+
+fn parse_arg<T: FromStr>(arg: &str) -> Result<T, String> {
+    arg.parse().map_err(|_| format!("invalid URL argument '{}'", arg))
+}
+
+macro_rules! url_arg_from_str {
+    ($($t:ty),*) => {
+        $(
+            impl FromUrlArgs for $t {
+                fn from_url_args(args: &[String]) -> Result<$t, String> {
+                    parse_arg(&args.join("/"))
+                }
+            }
+        )*
+    };
+}
+
+url_arg_from_str!(String, bool, char, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl FromUrlArgs for Vec<String> {
+    fn from_url_args(args: &[String]) -> Result<Vec<String>, String> {
+        Ok(args.to_vec())
+    }
+}
+
+macro_rules! url_args_tuple {
+    ($count:expr; $($t:ident $i:tt),*) => {
+        impl<$($t: FromStr),*> FromUrlArgs for ($($t,)*) {
+            fn from_url_args(args: &[String]) -> Result<($($t,)*), String> {
+                if args.len() != $count {
+                    return Err(format!("expected {} URL arguments but found {}", $count, args.len()));
+                }
+                Ok(($(parse_arg::<$t>(&args[$i])?,)*))
+            }
+        }
+    };
+}
+
+url_args_tuple!(1; A 0);
+url_args_tuple!(2; A 0, B 1);
+url_args_tuple!(3; A 0, B 1, C 2);
+url_args_tuple!(4; A 0, B 1, C 2, D 3);
+
+/// Types that can be built from decoded key/value pairs, used by the Query
+/// and Form extractors. Implemented for HashMap and BTreeMap of Strings, and
+/// your own types can implement it to pick out the fields they need.
+pub trait FromParams: Sized {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, String>;
+}
+
+impl FromParams for HashMap<String, String> {
+    fn from_params(params: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+        Ok(params.clone())
+    }
+}
+
+impl FromParams for BTreeMap<String, String> {
+    fn from_params(params: &HashMap<String, String>) -> Result<BTreeMap<String, String>, String> {
+        Ok(params.iter().map(|(key, value)| (key.clone(), value.clone())).collect())
+    }
+}
+
+/// Headers that can be read with the Header extractor. `name` is the header
+/// to look for and `parse` turns its value into the type.
+pub trait NamedHeader: Sized {
+    fn name() -> &'static str;
+    fn parse(value: &str) -> Result<Self, String>;
+}
+
+/// The `User-Agent` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent(pub String);
+
+impl NamedHeader for UserAgent {
+    fn name() -> &'static str {
+        "User-Agent"
+    }
+
+    fn parse(value: &str) -> Result<UserAgent, String> {
+        Ok(UserAgent(String::from(value)))
+    }
+}
+
+/// The `Authorization` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization(pub String);
+
+impl NamedHeader for Authorization {
+    fn name() -> &'static str {
+        "Authorization"
+    }
+
+    fn parse(value: &str) -> Result<Authorization, String> {
+        Ok(Authorization(String::from(value)))
+    }
+}
+
+/// The `Content-Length` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLength(pub u64);
+
+impl NamedHeader for ContentLength {
+    fn name() -> &'static str {
+        "Content-Length"
+    }
+
+    fn parse(value: &str) -> Result<ContentLength, String> {
+        value.trim().parse().map(ContentLength).map_err(|_| String::from("not a number"))
+    }
+}
+
+/// The route's URL arguments, parsed as `T`. Use `Path<u32>` for a route
+/// like `GET /users/{}` or a tuple for several arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<T>(pub T);
+
+impl<T: FromUrlArgs> FromRequest for Path<T> {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Path<T>, HttpError> {
+        let args: Vec<String> = request.get_url_arg_list().iter()
+            .map(|arg| http::url_decode(arg, false))
+            .collect();
+        T::from_url_args(&args)
+            .map(Path)
+            .map_err(|e| HttpError::new(400, &e))
+    }
+}
+
+/// The query parameters, decoded and parsed as `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T>(pub T);
+
+impl<T: FromParams> FromRequest for Query<T> {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Query<T>, HttpError> {
+        T::from_params(&http::parse_params(&request.get_query_string()))
+            .map(Query)
+            .map_err(|e| HttpError::new(400, &format!("Invalid query parameters: {}", e)))
+    }
+}
+
+/// A `application/x-www-form-urlencoded` request body, parsed as `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form<T>(pub T);
+
+impl<T: FromParams> FromRequest for Form<T> {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Form<T>, HttpError> {
+        T::from_params(&http::parse_params(&request.get_body_string()))
+            .map(Form)
+            .map_err(|e| HttpError::new(400, &format!("Invalid form body: {}", e)))
+    }
+}
+
+/// A JSON request body, parsed as `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: FromJson> FromRequest for Json<T> {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Json<T>, HttpError> {
+        JsonValue::parse(&request.get_body_string())
+            .and_then(|value| T::from_json(&value))
+            .map(Json)
+            .map_err(|e| HttpError::new(400, &format!("Invalid JSON body: {}", e)))
+    }
+}
+
+/// `Json` can be returned from handlers too, for any value that converts
+/// into a JsonValue.
+impl<T: Into<JsonValue>> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        self.0.into().into_response()
+    }
+}
+
+/// A single header, parsed as `T`. Missing or unparsable headers give a 400,
+/// so wrap it in an Option for headers that may be left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header<T>(pub T);
+
+impl<T: NamedHeader> FromRequest for Header<T> {
+    fn from_request(request: &Request, _: &Configuration) -> Result<Header<T>, HttpError> {
+        match request.get_header(T::name()) {
+            Some(value) => T::parse(&value)
+                .map(Header)
+                .map_err(|e| HttpError::new(400, &format!("Invalid {} header: {}", T::name(), e))),
+            None => Err(HttpError::new(400, &format!("Missing {} header", T::name()))),
+        }
+    }
+}
+
+/// Application state of type `T`, stored with `Configuration::with_state`.
+/// A missing value is a server mistake rather than the client's, so it
+/// gives a 500.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(_: &Request, configs: &Configuration) -> Result<State<T>, HttpError> {
+        configs.shared_state::<T>()
+            .map(State)
+            .ok_or_else(|| HttpError::new(500, "Missing application state"))
+    }
+}
+
+macro_rules! deref_extractor {
+    ($($extractor:ident),*) => {
+        $(
+            impl<T> Deref for $extractor<T> {
+                type Target = T;
+
+                fn deref(&self) -> &T {
+                    &self.0
+                }
+            }
+        )*
+    };
+}
+
+deref_extractor!(Path, Query, Form, Json, Header, State);
+
+/// Handler functions whose arguments are all extractors. `Args` is the tuple
+/// of argument types and only exists to keep the implementations apart.
+pub trait ExtractorHandler<Args>: Send + Sync {
+    fn call_with(&self, request: Request, configs: &Configuration) -> Response;
+}
+
+macro_rules! extractor_handler {
+    ($($t:ident),*) => {
+        impl<F, R, $($t),*> ExtractorHandler<($($t,)*)> for F
+            where F: Fn($($t),*) -> R + Send + Sync, R: IntoResponse, $($t: FromRequest),* {
+            #[allow(non_snake_case, unused_variables)]
+            fn call_with(&self, request: Request, configs: &Configuration) -> Response {
+                $(
+                    let $t = match $t::from_request(&request, configs) {
+                        Ok(value) => value,
+                        Err(e) => return configs.error_response(e.get_status(), &e.get_message(), &request),
+                    };
+                )*
+                self($($t),*).into_response()
+            }
+        }
+    };
+}
+
+extractor_handler!();
+extractor_handler!(A);
+extractor_handler!(A, B);
+extractor_handler!(A, B, C);
+extractor_handler!(A, B, C, D);
+extractor_handler!(A, B, C, D, E);
+extractor_handler!(A, B, C, D, E, G);
+
+/// Wraps a handler function with typed arguments so it can be added to
+/// Routes like any other handler:
+///
+/// fn show_user(Path(id): Path<u32>, State(db): State<MyDb>) -> Result<JsonValue, HttpError> { ... }
+/// routes.add_handler("GET /users/{}", extract(show_user));
+///
+pub fn extract<F, Args>(handler: F) -> Extractor<F, Args> where F: ExtractorHandler<Args> {
+    Extractor {
+        handler,
+        args: PhantomData,
+    }
+}
+
+/// A handler function with typed arguments, made by `extract`.
+pub struct Extractor<F, Args> {
+    handler: F,
+    args: PhantomData<fn() -> Args>,
+}
+
+impl<F, Args> Handler for Extractor<F, Args> where F: ExtractorHandler<Args> {
+    fn call(&self, request: Request, configs: &Configuration) -> Response {
+        self.handler.call_with(request, configs)
+    }
+}
+
+#[test]
+fn test_extract_path_query_and_state() {
+    use http::content_type::ContentType;
+    struct Prefix(String);
+    fn show(Path((id, section)): Path<(u32, String)>,
+            Query(params): Query<HashMap<String, String>>,
+            State(prefix): State<Prefix>) -> String {
+        format!("{}{} {} {}", prefix.0, id, section, params["q"])
+    }
+    let mut configs = Configuration::new().with_state(Prefix(String::from("#")));
+    configs.routes.add_handler("GET /users/{}", extract(show));
+    let request = Request::new()
+        .with_route(String::from("/users/7/my%20posts?q=a+b"));
+    let resp = super::route_request(request, &configs);
+    assert_eq!(resp, http::ok(String::from("#7 my posts a b"), ContentType::TextHtml));
+    let resp = super::route_request(Request::new().with_route(String::from("/users/x/y")), &configs);
+    assert_eq!(resp.get_status(), 400);
+}
+
+#[test]
+fn test_extract_json_form_and_headers() {
+    struct Missing;
+    fn create(Json(names): Json<Vec<String>>, agent: Option<Header<UserAgent>>) -> Json<Vec<String>> {
+        let mut names = names;
+        if let Some(Header(UserAgent(agent))) = agent {
+            names.push(agent);
+        }
+        Json(names)
+    }
+    fn login(Form(form): Form<BTreeMap<String, String>>, Header(length): Header<ContentLength>) -> String {
+        format!("{} {}", form["user"], length.0)
+    }
+    fn needs_state(_: State<Missing>) -> &'static str {
+        "unreachable"
+    }
+    let configs = Configuration::new().with_routes(super::Routes::new()
+        .with_handler("POST /names", extract(create))
+        .with_handler("POST /login", extract(login))
+        .with_handler("GET /state", extract(needs_state)));
+    let resp = super::route_request(Request::from("POST /names HTTP/1.1\r\nUser-Agent: curl\r\n\r\n[\"a\", \"b\"]"), &configs);
+    assert_eq!(resp.get_body(), b"[\"a\",\"b\",\"curl\"]".to_vec());
+    let resp = super::route_request(Request::from("POST /names HTTP/1.1\r\n\r\n{\"a\": 1}"), &configs);
+    assert_eq!(resp.get_status(), 400);
+    let deep = format!("POST /names HTTP/1.1\r\n\r\n{}", "[".repeat(200_000));
+    assert_eq!(super::route_request(Request::from(deep.as_str()), &configs).get_status(), 400);
+    let resp = super::route_request(Request::from("POST /login HTTP/1.1\r\nContent-Length: 16\r\n\r\nuser=ollie&x=%21"), &configs);
+    assert_eq!(resp.get_body(), b"ollie 16".to_vec());
+    let resp = super::route_request(Request::from("POST /login HTTP/1.1\r\n\r\nuser=ollie"), &configs);
+    assert_eq!(resp.get_status(), 400);
+    let resp = super::route_request(Request::from("GET /state HTTP/1.1\r\n\r\n"), &configs);
+    assert_eq!(resp.get_status(), 500);
+
+    let configs = configs.with_error_handler(400, |e: &HttpError, _: &Request, _: &Configuration| format!("bad: {}", e.get_message()));
+    let resp = super::route_request(Request::from("POST /names HTTP/1.1\r\n\r\n[1"), &configs);
+    assert_eq!(resp.get_status(), 400);
+    assert!(String::from_utf8(resp.get_body()).unwrap().starts_with("bad: Invalid JSON body: "));
+    let resp = super::route_request(Request::from("POST /names HTTP/1.1\r\nAccept: application/json\r\n\r\n[1"), &configs);
+    assert!(resp.get_body().starts_with(b"bad: "));
+    let configs = Configuration::new().with_routes(super::Routes::new().with_handler("POST /names", extract(create)));
+    let resp = super::route_request(Request::from("POST /names HTTP/1.1\r\nAccept: application/json\r\n\r\n[1"), &configs);
+    assert_eq!(resp.get_status(), 400);
+    assert_eq!(resp.get_content_type().stringify(), "application/json");
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use http::{IntoResponse, Response};
+use http::json::JsonValue;
+
+type Check = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+/// Named checks answering the liveness probe at `/healthz` and the
+/// readiness probe at `/readyz`. Each check returns `Ok(())` when healthy
+/// or `Err(reason)` when not. Readiness checks, added with `with_check`,
+/// cover things the server needs to take traffic, such as its database.
+/// Liveness checks, added with `with_liveness_check`, should only fail if
+/// the process needs restarting, and are run by both probes. Set them on
+/// the Configuration with `Configuration::with_health_checks`.
+#[derive(Clone, Default)]
+pub struct HealthChecks {
+    checks: Vec<(String, Check, bool)>,
+}
+
+impl HealthChecks {
+    pub fn new() -> HealthChecks {
+        HealthChecks::default()
+    }
+
+    /// Adds a check run by the readiness probe.
+    pub fn with_check<F>(mut self, name: &str, check: F) -> HealthChecks
+        where F: Fn() -> Result<(), String> + Send + Sync + 'static {
+        self.checks.push((String::from(name), Arc::new(check), false));
+        self
+    }
+
+    /// Adds a check run by both the liveness and readiness probes.
+    pub fn with_liveness_check<F>(mut self, name: &str, check: F) -> HealthChecks
+        where F: Fn() -> Result<(), String> + Send + Sync + 'static {
+        self.checks.push((String::from(name), Arc::new(check), true));
+        self
+    }
+
+    /// Returns the names of every check, in the order they were added.
+    pub fn get_check_names(&self) -> Vec<String> {
+        self.checks.iter().map(|check| check.0.clone()).collect()
+    }
+
+    /// Runs the liveness checks and builds the `/healthz` Response.
+    pub fn liveness(&self) -> Response {
+        self.run(true, false)
+    }
+
+    /// Runs every check and builds the `/readyz` Response. A server that is
+    /// shutting down is never ready, so it can be taken out of rotation
+    /// before it stops accepting connections.
+    pub fn readiness(&self, shutting_down: bool) -> Response {
+        self.run(false, shutting_down)
+    }
+
+    /// Runs the checks and reports them as
+    /// `{"status": "ok", "checks": {"database": {"status": "ok"}}}` with a 200,
+    /// or with a status of `"fail"` and a 503 if any check failed. A failing
+    /// check also reports its `error`, and a check that panics fails.
+    fn run(&self, liveness_only: bool, shutting_down: bool) -> Response {
+        let mut results = BTreeMap::new();
+        let mut healthy = true;
+        for (name, check, liveness) in &self.checks {
+            if liveness_only && !*liveness {
+                continue;
+            }
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| check()))
+                .unwrap_or_else(|_| Err(String::from("check panicked")));
+            let mut result = BTreeMap::new();
+            match outcome {
+                Ok(()) => {
+                    result.insert(String::from("status"), JsonValue::from("ok"));
+                },
+                Err(reason) => {
+                    healthy = false;
+                    result.insert(String::from("status"), JsonValue::from("fail"));
+                    result.insert(String::from("error"), JsonValue::from(reason));
+                },
+            }
+            results.insert(name.clone(), JsonValue::Object(result));
+        }
+        let status = if shutting_down {
+            "shutting_down"
+        } else if healthy {
+            "ok"
+        } else {
+            "fail"
+        };
+        let mut body = BTreeMap::new();
+        body.insert(String::from("status"), JsonValue::from(status));
+        body.insert(String::from("checks"), JsonValue::Object(results));
+        let code = if status == "ok" { 200 } else { 503 };
+        JsonValue::Object(body).into_response().with_status(code)
+    }
+}
+
+#[test]
+fn test_health_probes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use super::{Configuration, route_request};
+    use http::Request;
+    let get = |route: &str| Request::new().with_method(String::from("GET")).with_route(String::from(route));
+    let database_up = Arc::new(AtomicBool::new(true));
+    let database = database_up.clone();
+    let configs = Configuration::new().with_health_checks(HealthChecks::new()
+        .with_liveness_check("threads", || Ok(()))
+        .with_check("database", move || {
+            if database.load(Ordering::SeqCst) { Ok(()) } else { Err(String::from("connection refused")) }
+        })
+        .with_check("cache", || -> Result<(), String> { panic!("cache client bug") }));
+    assert_eq!(configs.health_checks.as_ref().unwrap().get_check_names(), vec!["threads", "database", "cache"]);
+
+    let resp = route_request(get("/healthz"), &configs);
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.get_body(), b"{\"checks\":{\"threads\":{\"status\":\"ok\"}},\"status\":\"ok\"}".to_vec());
+
+    let resp = route_request(get("/readyz"), &configs);
+    assert_eq!(resp.get_status(), 503);
+    let body = JsonValue::parse(&String::from_utf8(resp.get_body()).unwrap()).unwrap();
+    assert_eq!(body.get("status").and_then(|s| s.as_str()), Some("fail"));
+    let checks = body.get("checks").unwrap();
+    assert_eq!(checks.get("database").and_then(|c| c.get("status")).and_then(|s| s.as_str()), Some("ok"));
+    assert_eq!(checks.get("cache").and_then(|c| c.get("error")).and_then(|e| e.as_str()), Some("check panicked"));
+
+    let configs = Configuration::new().with_health_checks(HealthChecks::new().with_check("database", move || {
+        if database_up.load(Ordering::SeqCst) { Ok(()) } else { Err(String::from("connection refused")) }
+    }));
+    assert_eq!(route_request(get("/readyz"), &configs).get_status(), 200);
+    configs.shutdown_handle().shutdown();
+    let resp = route_request(get("/readyz"), &configs);
+    assert_eq!(resp.get_status(), 503);
+    assert!(String::from_utf8(resp.get_body()).unwrap().contains("\"status\":\"shutting_down\""));
+    assert_eq!(route_request(get("/healthz"), &configs).get_status(), 200);
+    assert_eq!(route_request(get("/readyz"), &Configuration::new()).get_status(), 404);
+}
+
+#[test]
+fn test_run_returns_after_shutdown() {
+    use std::thread;
+    use std::time::Duration;
+    use super::{Configuration, Server, Servo};
+    let servo = Servo::new().with_configuration(Configuration::new()
+        .with_server_configurations(Server::new().with_port("0").with_shutdown_delay(Duration::from_millis(20))));
+    let handle = servo.shutdown_handle();
+    let running = thread::spawn(move || servo.run());
+    thread::sleep(Duration::from_millis(50));
+    assert!(!handle.is_shutting_down());
+    handle.shutdown();
+    assert!(handle.is_shutting_down());
+    running.join().unwrap();
+}
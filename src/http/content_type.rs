@@ -5,10 +5,12 @@ use std::ffi::OsStr;
 #[derive(Copy, Clone, Debug, Eq)]
 pub enum ContentType {
     TextHtml,
+    TextPlain,
     TextCss,
     TextJs,
     TextSvgXml,
     MultipartForm,
+    MultipartByteranges,
     ImagePng,
     ImageJpg,
     ImageBmp,
@@ -23,10 +25,12 @@ impl ContentType {
     pub fn stringify(&self) -> String {
         match self {
             &ContentType::TextHtml => String::from("text/html"),
+            &ContentType::TextPlain => String::from("text/plain"),
             &ContentType::TextCss => String::from("text/css"),
             &ContentType::TextJs => String::from("text/javascript"),
             &ContentType::TextSvgXml => String::from("text/svg+xml"),
             &ContentType::MultipartForm => String::from("multipart/form"),
+            &ContentType::MultipartByteranges => String::from("multipart/byteranges"),
             &ContentType::ImageJpg => String::from("image/jpg"),
             &ContentType::ImagePng => String::from("image/png"),
             &ContentType::ImageBmp => String::from("image/bmp"),
@@ -64,6 +68,7 @@ fn get_file_type_from_extension(ext: &str) -> ContentType {
         "gif" => ContentType::ImageGif,
         "bmp" => ContentType::ImageBmp,
         "css" => ContentType::TextCss,
+        "txt" => ContentType::TextPlain,
         "js" => ContentType::TextJs,
         "svg" => ContentType::TextSvgXml,
         "json" => ContentType::ApplicationJson,
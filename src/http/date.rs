@@ -0,0 +1,213 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                            "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// A UTC date and time to the second, used for the timestamps Servo writes
+/// in logs and headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    /// Converts a count of seconds since the Unix epoch.
+    pub fn from_unix(seconds: i64) -> DateTime {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u32,
+            minute: (time % 3600 / 60) as u32,
+            second: (time % 60) as u32,
+        }
+    }
+
+    /// Converts a SystemTime, dropping anything below a second.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => DateTime::from_unix(since.as_secs() as i64),
+            Err(e) => {
+                let before = e.duration();
+                let mut seconds = -(before.as_secs() as i64);
+                if before.subsec_nanos() > 0 {
+                    seconds -= 1;
+                }
+                DateTime::from_unix(seconds)
+            },
+        }
+    }
+
+    pub fn now() -> DateTime {
+        DateTime::from_system_time(SystemTime::now())
+    }
+
+    /// Returns the number of seconds since the Unix epoch.
+    pub fn to_unix(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let seconds = self.to_unix();
+        if seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(seconds as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+        }
+    }
+
+    pub fn get_year(&self) -> i64 {
+        self.year
+    }
+
+    pub fn get_month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn get_day(&self) -> u32 {
+        self.day
+    }
+
+    pub fn get_hour(&self) -> u32 {
+        self.hour
+    }
+
+    pub fn get_minute(&self) -> u32 {
+        self.minute
+    }
+
+    pub fn get_second(&self) -> u32 {
+        self.second
+    }
+
+    /// Formats the time as used by the Common Log Format, such as
+    /// `10/Oct/2000:13:55:36 +0000`.
+    pub fn to_clf(&self) -> String {
+        format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+                self.day, MONTHS[self.month as usize - 1], self.year,
+                self.hour, self.minute, self.second)
+    }
+
+    /// Formats the time as ISO 8601, such as `2000-10-10T13:55:36Z`.
+    pub fn to_iso8601(&self) -> String {
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+
+    /// Formats the time as an HTTP date in the IMF-fixdate format used by
+    /// headers such as `Last-Modified`, for example
+    /// `Tue, 10 Oct 2000 13:55:36 GMT`.
+    pub fn to_http_date(&self) -> String {
+        let weekday = (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7);
+        format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+                WEEKDAYS[weekday as usize], self.day, MONTHS[self.month as usize - 1], self.year,
+                self.hour, self.minute, self.second)
+    }
+
+    /// Parses an HTTP date in any of the three formats clients may send:
+    /// IMF-fixdate (`Tue, 10 Oct 2000 13:55:36 GMT`), the obsolete RFC 850
+    /// format (`Tuesday, 10-Oct-00 13:55:36 GMT`) and asctime
+    /// (`Tue Oct 10 13:55:36 2000`). A two digit RFC 850 year is taken to be
+    /// in the past century if it would otherwise be more than 50 years in
+    /// the future. The weekday isn't checked. Returns None if the value
+    /// isn't a valid date in one of these formats.
+    pub fn parse_http_date(value: &str) -> Option<DateTime> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        match parts.len() {
+            6 if parts[0].ends_with(',') && parts[5] == "GMT" => {
+                let year = parse_number(parts[3], 4, 4)?;
+                DateTime::from_parts(year, parts[2], parts[1], parts[4])
+            },
+            4 if parts[0].ends_with(',') && parts[3] == "GMT" => {
+                let date: Vec<&str> = parts[1].split('-').collect();
+                if date.len() != 3 {
+                    return None;
+                }
+                let short_year = parse_number(date[2], 2, 2)?;
+                let this_year = DateTime::now().year;
+                let mut year = this_year - this_year.rem_euclid(100) + short_year;
+                if year > this_year + 50 {
+                    year -= 100;
+                }
+                DateTime::from_parts(year, date[1], date[0], parts[2])
+            },
+            5 => {
+                let year = parse_number(parts[4], 4, 4)?;
+                DateTime::from_parts(year, parts[1], parts[2], parts[3])
+            },
+            _ => None,
+        }
+    }
+
+    /// Builds a date from a year, a month name, a day and an `hh:mm:ss`
+    /// time, checking every part is in range.
+    fn from_parts(year: i64, month: &str, day: &str, time: &str) -> Option<DateTime> {
+        let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+        let day = parse_number(day, 1, 2)? as u32;
+        let time: Vec<&str> = time.split(':').collect();
+        if time.len() != 3 {
+            return None;
+        }
+        let date = DateTime {
+            year,
+            month,
+            day,
+            hour: parse_number(time[0], 2, 2)? as u32,
+            minute: parse_number(time[1], 2, 2)? as u32,
+            second: parse_number(time[2], 2, 2)? as u32,
+        };
+        // Catches days past the end of the month as well as bad times.
+        if date.hour > 23 || date.minute > 59 || date.second > 59 || DateTime::from_unix(date.to_unix()) != date {
+            return None;
+        }
+        Some(date)
+    }
+}
+
+/// Parses a number written with between `min` and `max` ASCII digits.
+fn parse_number(digits: &str, min: usize, max: usize) -> Option<i64> {
+    if digits.len() < min || digits.len() > max || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a date in the proleptic Gregorian calendar to days since the
+/// Unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,484 @@
+use std::collections::BTreeMap;
+
+/// A JSON value that Servo can read from a request body or write into a
+/// response body. Values can be built directly or converted from Rust values
+/// with `JsonValue::from`, and a JsonValue returned from a handler becomes an
+/// `application/json` Response.
+///
+/// let user = JsonValue::object(vec![
+///     ("id", JsonValue::from(42)),
+///     ("name", JsonValue::from("Ollie")),
+/// ]);
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    /// Builds a JSON object from key/value pairs.
+    pub fn object(pairs: Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(pairs.into_iter()
+            .map(|(key, value)| (String::from(key), value))
+            .collect())
+    }
+
+    /// Parses JSON text into a value. Returns a description of the problem
+    /// if the text isn't valid JSON, or nests arrays and objects more than
+    /// `MAX_DEPTH` deep.
+    pub fn parse(text: &str) -> Result<JsonValue, String> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0, depth: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(format!("unexpected '{}' after JSON value", parser.chars[parser.position]));
+        }
+        Ok(value)
+    }
+
+    /// Looks up a key on an object. Returns None for missing keys and for
+    /// values that aren't objects.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match *self {
+            JsonValue::Object(ref pairs) => pairs.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            JsonValue::String(ref value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            JsonValue::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            JsonValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match *self {
+            JsonValue::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == JsonValue::Null
+    }
+
+    /// Transforms the value into its JSON text.
+    pub fn stringify(&self) -> String {
+        let mut result = String::new();
+        self.write_to(&mut result);
+        result
+    }
+
+    fn write_to(&self, out: &mut String) {
+        match *self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(value) => out.push_str(if value { "true" } else { "false" }),
+            JsonValue::Number(value) => out.push_str(&stringify_number(value)),
+            JsonValue::String(ref value) => write_string(value, out),
+            JsonValue::Array(ref values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.write_to(out);
+                }
+                out.push(']');
+            },
+            JsonValue::Object(ref pairs) => {
+                out.push('{');
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write_to(out);
+                }
+                out.push('}');
+            },
+        }
+    }
+}
+
+/// Numbers without a fractional part are written without one. JSON has no
+/// way to write NaN or infinity, so those become `null`.
+fn stringify_number(value: f64) -> String {
+    if !value.is_finite() {
+        String::from("null")
+    } else if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> JsonValue {
+        JsonValue::Bool(value)
+    }
+}
+
+macro_rules! json_number_from {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for JsonValue {
+                fn from(value: $t) -> JsonValue {
+                    JsonValue::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+json_number_from!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<'a> From<&'a str> for JsonValue {
+    fn from(value: &'a str) -> JsonValue {
+        JsonValue::String(String::from(value))
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> JsonValue {
+        JsonValue::String(value)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(values: Vec<T>) -> JsonValue {
+        JsonValue::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> JsonValue {
+        match value {
+            Some(value) => value.into(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T: Into<JsonValue>> From<BTreeMap<String, T>> for JsonValue {
+    fn from(pairs: BTreeMap<String, T>) -> JsonValue {
+        JsonValue::Object(pairs.into_iter().map(|(key, value)| (key, value.into())).collect())
+    }
+}
+
+/// Types that can be built from a parsed JsonValue, used to read typed JSON
+/// request bodies. Implemented for JsonValue itself, booleans, numbers,
+/// Strings, and Vecs, Options and String-keyed BTreeMaps of those. Your own
+/// types can implement it by pulling their fields out of the value:
+///
+/// impl FromJson for NewUser {
+///     fn from_json(value: &JsonValue) -> Result<NewUser, String> {
+///         Ok(NewUser {
+///             name: String::from_json(value.get("name").unwrap_or(&JsonValue::Null))?,
+///         })
+///     }
+/// }
+///
+pub trait FromJson: Sized {
+    fn from_json(value: &JsonValue) -> Result<Self, String>;
+}
+
+impl FromJson for JsonValue {
+    fn from_json(value: &JsonValue) -> Result<JsonValue, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &JsonValue) -> Result<bool, String> {
+        value.as_bool().ok_or_else(|| String::from("expected a boolean"))
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: &JsonValue) -> Result<String, String> {
+        value.as_str().map(String::from).ok_or_else(|| String::from("expected a string"))
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &JsonValue) -> Result<f64, String> {
+        value.as_f64().ok_or_else(|| String::from("expected a number"))
+    }
+}
+
+impl FromJson for f32 {
+    fn from_json(value: &JsonValue) -> Result<f32, String> {
+        f64::from_json(value).map(|number| number as f32)
+    }
+}
+
+macro_rules! json_integer_from_json {
+    ($($t:ty),*) => {
+        $(
+            impl FromJson for $t {
+                fn from_json(value: &JsonValue) -> Result<$t, String> {
+                    let number = f64::from_json(value)?;
+                    if number.fract() != 0.0 || number < <$t>::min_value() as f64 || number > <$t>::max_value() as f64 {
+                        return Err(format!("expected a whole number that fits in {}", stringify!($t)));
+                    }
+                    Ok(number as $t)
+                }
+            }
+        )*
+    };
+}
+
+json_integer_from_json!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &JsonValue) -> Result<Vec<T>, String> {
+        match value.as_array() {
+            Some(values) => values.iter().map(T::from_json).collect(),
+            None => Err(String::from("expected an array")),
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &JsonValue) -> Result<Option<T>, String> {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            T::from_json(value).map(Some)
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for BTreeMap<String, T> {
+    fn from_json(value: &JsonValue) -> Result<BTreeMap<String, T>, String> {
+        match *value {
+            JsonValue::Object(ref pairs) => pairs.iter()
+                .map(|(key, value)| T::from_json(value).map(|value| (key.clone(), value)))
+                .collect(),
+            _ => Err(String::from("expected an object")),
+        }
+    }
+}
+
+/// How deeply arrays and objects may be nested in parsed JSON. The parser
+/// recurses for each level, so without a limit a request body of nothing
+/// but `[` would overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+/// Recursive descent parser behind `JsonValue::parse`.
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but the input ended", expected)),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c @ '{') | Some(c @ '[') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(String::from("nesting too deep"));
+                }
+                self.depth += 1;
+                let value = if c == '{' { self.parse_object() } else { self.parse_array() };
+                self.depth -= 1;
+                value
+            },
+            Some('"') => self.parse_string().map(JsonValue::String),
+            Some('t') => self.expect_word("true", JsonValue::Bool(true)),
+            Some('f') => self.expect_word("false", JsonValue::Bool(false)),
+            Some('n') => self.expect_word("null", JsonValue::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err(String::from("unexpected end of input")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        self.expect('{')?;
+        let mut pairs = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(pairs));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            pairs.insert(key, value);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(JsonValue::Object(pairs)),
+                _ => return Err(String::from("expected ',' or '}' in object")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(JsonValue::Array(values)),
+                _ => return Err(String::from("expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(result),
+                Some('\\') => {
+                    match self.next() {
+                        Some('"') => result.push('"'),
+                        Some('\\') => result.push('\\'),
+                        Some('/') => result.push('/'),
+                        Some('b') => result.push('\u{8}'),
+                        Some('f') => result.push('\u{c}'),
+                        Some('n') => result.push('\n'),
+                        Some('r') => result.push('\r'),
+                        Some('t') => result.push('\t'),
+                        Some('u') => result.push(self.parse_unicode_escape()?),
+                        _ => return Err(String::from("invalid escape in string")),
+                    }
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(String::from("control character in string")),
+                Some(c) => result.push(c),
+                None => return Err(String::from("unterminated string")),
+            }
+        }
+    }
+
+    /// Reads the hex digits after `\u`, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let first = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&first) {
+            self.expect('\\')?;
+            self.expect('u')?;
+            let second = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&second) {
+                return Err(String::from("invalid surrogate pair in string"));
+            }
+            0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+        } else {
+            first
+        };
+        ::std::char::from_u32(code).ok_or_else(|| String::from("invalid unicode escape in string"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| String::from("invalid unicode escape in string"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| format!("invalid number '{}'", text))
+    }
+}
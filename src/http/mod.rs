@@ -1,20 +1,43 @@
 pub mod content_type;
+pub mod date;
+pub mod json;
 
 use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+use std::num::{ParseFloatError, ParseIntError};
+use std::process;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use self::content_type::ContentType;
+use self::json::JsonValue;
+
+/// HTTP status codes are plain integers throughout Servo, such as `404`.
+pub type StatusCode = i32;
 
 /// Request struct that contains the elements
 /// of the request given to Servo. `url_args` contain
 /// the arguments of any route setup using a wildcard ({})
 /// character. `query_params` are for query parameters
-/// passed in by the client via `host.com?arg1=val1&arg2=val2`
-#[derive(Eq,Debug)]
+/// passed in by the client via `host.com?arg1=val1&arg2=val2`.
+/// `mount_point` is the prefix that was stripped from the route when
+/// the request was passed to a mounted route table. `body` holds
+/// everything after the headers. `request_id` identifies the request in
+/// logs and is echoed back to the client in the `X-Request-Id` header.
+#[derive(Eq,Debug,Clone)]
 pub struct Request {
     method : String,
     route : String,
     headers : HashMap<String, String>,
     url_args : Vec<String>,
     query_params : HashMap<String, String>,
+    mount_point : String,
+    body : Vec<u8>,
+    request_id : String,
 }
 
 /// Response struct that contains everything
@@ -26,12 +49,31 @@ pub struct Request {
 ///     .with_content_type(servo::http::content_type::ContentType::TextHtml)
 ///     .with_body(Vec::new());
 ///
-#[derive(Eq,Debug)]
+/// `route_pattern` is the route that answered the request, such as
+/// `/users/{}`, which Servo fills in for its metrics. It isn't sent to the
+/// client or compared. A Response built `with_stream` has no `body`, its
+/// body is read from `stream` while it is written to the client instead.
+#[derive(Debug)]
 pub struct Response {
     status : i32,
     content_type : ContentType,
     body : Vec<u8>,
     headers : Option<HashMap<String, String>>,
+    route_pattern : Option<String>,
+    stream : Option<BodyStream>,
+}
+
+/// A body read from a reader as it is sent, along with how many bytes the
+/// reader will give if that is known.
+struct BodyStream {
+    reader : Box<dyn Read + Send>,
+    length : Option<u64>,
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BodyStream").field("length", &self.length).finish()
+    }
 }
 
 impl PartialEq for Request {
@@ -41,10 +83,14 @@ impl PartialEq for Request {
         && self.headers == other.headers
         && self.url_args == other.url_args
         && self.query_params == other.query_params
+        && self.mount_point == other.mount_point
+        && self.body == other.body
+        && self.request_id == other.request_id
     }
 }
 
-// Comparing content types causes stack overflow
+// Comparing content types causes stack overflow, and streams can't be
+// compared without reading them.
 impl PartialEq for Response {
     fn eq(&self, other: &Response) -> bool {
         self.status == other.status
@@ -54,6 +100,8 @@ impl PartialEq for Response {
     }
 }
 
+impl Eq for Response {}
+
 /// Builds a Response struct from a given body and content type with a status = 404
 pub fn not_found<'a>(body: String, content_type: ContentType) -> Response {
     Response::new()
@@ -86,6 +134,237 @@ pub fn server_error<'a>(body: String, content_type: ContentType) -> Response {
         .with_body(Vec::from(body.as_bytes()))
 }
 
+/// Generates a random request ID in the form of a version 4 UUID, such as
+/// `3f2b8c1e-9a4d-4e07-b1c6-5d8e2f7a9c30`.
+pub fn generate_request_id() -> String {
+    static GENERATED: AtomicUsize = AtomicUsize::new(0);
+    let count = GENERATED.fetch_add(1, Ordering::SeqCst);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|since| since.subsec_nanos())
+        .unwrap_or(0);
+    let mut halves = [0u64; 2];
+    for (index, half) in halves.iter_mut().enumerate() {
+        // Every RandomState is seeded differently, so this is unpredictable
+        // even for IDs generated in the same nanosecond.
+        *half = RandomState::new().hash_one((count, nanos, index, process::id()));
+    }
+    let high = (halves[0] & 0xffff_ffff_ffff_0fff) | 0x4000;
+    let low = (halves[1] & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+    format!("{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32, (high >> 16) & 0xffff, high & 0xffff, low >> 48, low & 0xffff_ffff_ffff)
+}
+
+/// Inbound request IDs are accepted if they are up to 128 visible ASCII
+/// characters, so they can't break up headers or log lines.
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= 128
+        && request_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Escapes the characters that have a meaning in HTML so that text can be
+/// placed inside a page or an attribute value.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Anything a handler can return. Servo turns the value into the Response
+/// sent to the client. Implemented for:
+///
+/// Response: sent as is
+/// String and &'static str: 200 with a `text/html` body
+/// JsonValue: 200 with an `application/json` body
+/// (StatusCode, body): the body's Response with the given status
+/// Result<T, E>: either side's Response, so handlers can use `?` freely
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        ok(self, ContentType::TextHtml)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        ok(String::from(self), ContentType::TextHtml)
+    }
+}
+
+impl IntoResponse for JsonValue {
+    fn into_response(self) -> Response {
+        ok(self.stringify(), ContentType::ApplicationJson)
+    }
+}
+
+impl<B: IntoResponse> IntoResponse for (StatusCode, B) {
+    fn into_response(self) -> Response {
+        self.1.into_response().with_status(self.0)
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+/// A general purpose error for handlers that return `Result`. It becomes a
+/// Response with its status and message, and common errors convert into it
+/// so they can be passed up with `?`: files that can't be found give a 404,
+/// values that don't parse give a 400 and other I/O errors give a 500.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HttpError {
+    status: StatusCode,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: &str) -> HttpError {
+        HttpError {
+            status,
+            message: String::from(message),
+        }
+    }
+
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn get_message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl Error for HttpError {}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        Response::new()
+            .with_status(self.status)
+            .with_content_type(ContentType::TextHtml)
+            .with_body(self.message.into_bytes())
+    }
+}
+
+impl From<io::Error> for HttpError {
+    fn from(error: io::Error) -> HttpError {
+        match error.kind() {
+            io::ErrorKind::NotFound => HttpError::new(404, "Could not find resource"),
+            _ => HttpError::new(500, &error.to_string()),
+        }
+    }
+}
+
+impl From<ParseIntError> for HttpError {
+    fn from(error: ParseIntError) -> HttpError {
+        HttpError::new(400, &error.to_string())
+    }
+}
+
+impl From<ParseFloatError> for HttpError {
+    fn from(error: ParseFloatError) -> HttpError {
+        HttpError::new(400, &error.to_string())
+    }
+}
+
+/// Returns the index where the body of a raw HTTP request starts, just
+/// past the blank line that ends the headers, or None if the headers
+/// haven't ended yet.
+pub fn find_body_start(request: &[u8]) -> Option<usize> {
+    for i in 0..request.len() {
+        if request[i..].starts_with(b"\r\n\r\n") {
+            return Some(i + 4);
+        }
+        if request[i..].starts_with(b"\n\n") {
+            return Some(i + 2);
+        }
+    }
+    None
+}
+
+/// Decodes a percent-encoded string from a URL. When `plus_as_space` is
+/// set, `+` is decoded as a space, as in form bodies and query strings.
+/// Sequences that aren't valid percent-encoding are left as they are.
+pub fn url_decode(value: &str, plus_as_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Splits a query string or form body such as `a=1&b=two` into decoded
+/// key/value pairs. Keys without a value are given an empty one.
+pub fn parse_params(params: &str) -> HashMap<String, String> {
+    params.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            (url_decode(key, true), url_decode(value, true))
+        })
+        .collect()
+}
+
+/// Percent-encodes a string for use in a URL. Everything except ASCII letters,
+/// digits and `-`, `.`, `_`, `~` is encoded.
+pub fn url_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
 impl Request {
     /// Creates a new request with an empty route and GET method.
     pub fn new() -> Request {
@@ -95,6 +374,9 @@ impl Request {
             headers : HashMap::new(),
             url_args : Vec::new(),
             query_params : HashMap::new(),
+            mount_point : String::new(),
+            body : Vec::new(),
+            request_id : String::new(),
         }
     }
 
@@ -127,7 +409,11 @@ impl Request {
     /// Creates a Request object from a HTTP request.
     pub fn from(request : &str) -> Request {
         let request = request.trim_left();
-        let lines = request.lines();
+        let (head, body) = match find_body_start(request.as_bytes()) {
+            Some(index) => (&request[..index], &request[index..]),
+            None => (request, ""),
+        };
+        let lines = head.lines();
         let mut i = 0;
         let mut found_method : String = String::default();
         let mut found_route : String = String::default();
@@ -142,7 +428,7 @@ impl Request {
                 }
             }
             else {
-                let pair : Vec<&str> = line.split(":").collect();
+                let pair : Vec<&str> = line.splitn(2, ":").collect();
                 if pair.len() > 1 {
                     let key = pair[0];
                     let value = pair[1].trim_left();
@@ -155,14 +441,73 @@ impl Request {
                                   .with_method(found_method)
                                   .with_route(found_route)
                                   .with_headers(found_headers)
+                                  .with_body(Vec::from(body.as_bytes()))
                                   .query_params_from_route();
         new_request
     }
 
+    /// Creates a Request object from the raw bytes of a HTTP request. Unlike
+    /// `from`, the body is kept exactly as given even if it isn't UTF-8.
+    pub fn from_bytes(request: &[u8]) -> Request {
+        match find_body_start(request) {
+            Some(index) => {
+                Request::from(&String::from_utf8_lossy(&request[..index]))
+                    .with_body(Vec::from(&request[index..]))
+            },
+            None => Request::from(&String::from_utf8_lossy(request)),
+        }
+    }
+
+    /// Creates a Request object from the raw bytes of a HTTP request, failing
+    /// with a 400 if the request line isn't a method followed by a target
+    /// and an optional HTTP version.
+    pub fn parse(request: &[u8]) -> Result<Request, HttpError> {
+        let head = match find_body_start(request) {
+            Some(index) => String::from_utf8_lossy(&request[..index]),
+            None => String::from_utf8_lossy(request),
+        };
+        let request_line = head.trim_start().lines().next().unwrap_or("");
+        let parts: Vec<&str> = request_line.split_whitespace().collect();
+        let valid = (parts.len() == 2 || parts.len() == 3)
+            && parts[0].bytes().all(|b| b.is_ascii_uppercase())
+            && (parts[1].starts_with('/') || parts[1] == "*")
+            && (parts.len() == 2 || parts[2].starts_with("HTTP/"));
+        if valid {
+            Ok(Request::from_bytes(request))
+        } else {
+            Err(HttpError::new(400, "Malformed request."))
+        }
+    }
+
     pub fn get_method(&self) -> String {
         self.method.clone()
     }
 
+    /// Returns the ID Servo gave this request, or an empty String for a
+    /// Request that hasn't been routed yet.
+    pub fn get_request_id(&self) -> String {
+        self.request_id.clone()
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Request {
+        self.request_id = String::from(request_id);
+        self
+    }
+
+    /// Gives the request an ID if it doesn't have one, taken from its
+    /// `X-Request-Id` header so IDs can follow a request between services,
+    /// or generated if that header is missing or isn't a usable ID.
+    pub fn ensure_request_id(self) -> Request {
+        if !self.request_id.is_empty() {
+            return self;
+        }
+        let request_id = match self.get_header("X-Request-Id") {
+            Some(ref inbound) if is_valid_request_id(inbound) => inbound.clone(),
+            _ => generate_request_id(),
+        };
+        self.with_request_id(&request_id)
+    }
+
     /// Returns the route including method as it was given in the request
     /// to Servo.
     pub fn get_route(&self) -> String {
@@ -173,10 +518,37 @@ impl Request {
         route
     }
 
+    /// Returns the requested path without the method or any query string.
+    pub fn get_path(&self) -> String {
+        match self.route.find('?') {
+            Some(index) => String::from(&self.route[..index]),
+            None => self.route.clone(),
+        }
+    }
+
     pub fn get_headers(&self) -> HashMap<String, String> {
         self.headers.clone()
     }
 
+    /// Returns the value of a single header, ignoring the case of its name.
+    pub fn get_header(&self, name: &str) -> Option<String> {
+        self.headers.iter()
+            .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Returns the host the request was sent to, taken from the `Host` header
+    /// in lowercase and without a port. Empty if the header is missing.
+    pub fn get_host(&self) -> String {
+        let host = self.get_header("Host").unwrap_or_default().trim().to_lowercase();
+        let end = if host.starts_with('[') {
+            host.find(']').map(|index| index + 1).unwrap_or_else(|| host.len())
+        } else {
+            host.find(':').unwrap_or(host.len())
+        };
+        String::from(&host[..end])
+    }
+
     pub fn get_url_args(&self) -> String {
         let url_args = self.url_args.clone().join("/");
         url_args
@@ -186,6 +558,118 @@ impl Request {
         self.query_params.clone()
     }
 
+    /// Returns the URL arguments one per wildcard segment, rather than
+    /// joined into a single String like `get_url_args`. The default router
+    /// leaves the query string on the last argument, so it is dropped here.
+    pub fn get_url_arg_list(&self) -> Vec<String> {
+        let mut args = self.url_args.clone();
+        if let Some(last) = args.last_mut() {
+            if let Some(index) = last.find('?') {
+                last.truncate(index);
+            }
+        }
+        args
+    }
+
+    /// Returns true if the `Accept` header ranks JSON above HTML, for
+    /// responses that can be sent as either.
+    pub fn prefers_json(&self) -> bool {
+        let accept = match self.get_header("Accept") {
+            Some(accept) => accept.to_lowercase(),
+            None => return false,
+        };
+        let mut json_quality = 0.0;
+        let mut html_quality = 0.0;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or("").trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .filter_map(|quality| quality.parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+            if media_type == "application/json" || media_type.ends_with("+json") {
+                json_quality = f32::max(json_quality, quality);
+            } else if media_type == "text/html" || media_type == "text/*" || media_type == "*/*" {
+                html_quality = f32::max(html_quality, quality);
+            }
+        }
+        json_quality > html_quality
+    }
+
+    /// Returns true if the `Accept` header explicitly asks for HTML, as
+    /// browsers do when navigating to a page. A bare `*/*`, which scripts
+    /// fetching data usually send, doesn't count.
+    pub fn accepts_html(&self) -> bool {
+        let accept = match self.get_header("Accept") {
+            Some(accept) => accept.to_lowercase(),
+            None => return false,
+        };
+        accept.split(',').any(|range| {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or("").trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .filter_map(|quality| quality.parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+            (media_type == "text/html" || media_type == "application/xhtml+xml") && quality > 0.0
+        })
+    }
+
+    /// Returns how much the client wants responses in the given content
+    /// coding, such as `gzip`, from 0 to 1, according to its
+    /// `Accept-Encoding` header. Codings the header doesn't mention get the
+    /// quality of `*` if it is there, or 0, except for `identity` which is
+    /// acceptable unless refused.
+    pub fn encoding_quality(&self, coding: &str) -> f32 {
+        let mut wildcard = None;
+        if let Some(accept) = self.get_header("Accept-Encoding") {
+            for range in accept.split(',') {
+                let mut params = range.split(';');
+                let name = params.next().unwrap_or("").trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .filter_map(|quality| quality.parse::<f32>().ok())
+                    .next()
+                    .unwrap_or(1.0);
+                if name.eq_ignore_ascii_case(coding) {
+                    return quality;
+                } else if name == "*" {
+                    wildcard = Some(quality);
+                }
+            }
+        }
+        match wildcard {
+            Some(quality) => quality,
+            None if coding.eq_ignore_ascii_case("identity") => 1.0,
+            None => 0.0,
+        }
+    }
+
+    /// Returns the raw query string, without the leading `?`.
+    pub fn get_query_string(&self) -> String {
+        match self.route.find('?') {
+            Some(index) => String::from(&self.route[index + 1..]),
+            None => String::new(),
+        }
+    }
+
+    pub fn get_body(&self) -> Vec<u8> {
+        self.body.clone()
+    }
+
+    /// Returns the body as text, replacing anything that isn't UTF-8.
+    pub fn get_body_string(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Returns the prefix the request was mounted under, in the format
+    /// `/billing`. Empty if the request was not routed through a mount.
+    pub fn get_mount_point(&self) -> String {
+        self.mount_point.clone()
+    }
+
     // Request setters
     pub fn with_method(mut self, req_method: String) -> Request {
         self.method = req_method;
@@ -231,6 +715,28 @@ impl Request {
         self.query_params.insert(req_param.0, req_param.1);
         self
     }
+
+    pub fn with_body(mut self, req_body: Vec<u8>) -> Request {
+        self.body = req_body;
+        self
+    }
+
+    pub fn with_mount_point(mut self, mount_point: String) -> Request {
+        self.mount_point = mount_point;
+        self
+    }
+
+    /// Strips the given prefix (in the format `/billing`) from the front of
+    /// the route and appends it to the mount point. The route is left as `/`
+    /// if nothing else remains.
+    pub fn mounted_at(mut self, prefix: &str) -> Request {
+        if self.route.starts_with(prefix) {
+            let rest = String::from(&self.route[prefix.len()..]);
+            self.route = if rest.starts_with('/') { rest } else { format!("/{}", rest) };
+            self.mount_point.push_str(prefix);
+        }
+        self
+    }
 }
 
 impl Response {
@@ -240,7 +746,9 @@ impl Response {
             status: 0_i32,
             content_type: ContentType::TextHtml,
             body: Vec::new(),
-            headers: None
+            headers: None,
+            route_pattern: None,
+            stream: None,
         }
     }
 
@@ -249,9 +757,25 @@ impl Response {
     fn stringify(&mut self) -> String {
         let status = self.status.clone();
         let content_type = self.content_type.clone();
-        let body_size = self.body.len();
-        self.add_header("Content-Type", &content_type.stringify());
-        self.add_header("Content-Length", &body_size.to_string());
+        let body_size = match self.stream {
+            Some(ref stream) => stream.length,
+            None => Some(self.body.len() as u64),
+        };
+        // A 304 describes the cached copy, so it mustn't claim an empty body.
+        if status != 304 {
+            // A Content-Type header set by hand wins, so parameters such as a
+            // multipart boundary can be given.
+            let has_content_type = self.headers.as_ref()
+                .map(|headers| headers.keys().any(|key| key.eq_ignore_ascii_case("Content-Type")))
+                .unwrap_or(false);
+            if !has_content_type {
+                self.add_header("Content-Type", &content_type.stringify());
+            }
+            match body_size {
+                Some(body_size) => self.add_header("Content-Length", &body_size.to_string()),
+                None => self.add_header("Transfer-Encoding", "chunked"),
+            }
+        }
         let mut res = String::from(format!("HTTP/1.1 {}\n\r", status));
         if self.headers.is_some() {
             let headers = self.headers.as_ref().unwrap();
@@ -263,14 +787,71 @@ impl Response {
         res
     }
 
-    /// Transforms the whole request into a vector of bytes (Vec<u8>)
-    pub fn byteify(mut self) -> Vec<u8> {
-        let part1 = self.stringify();
-        let mut result: Vec<u8> = Vec::from(part1.as_bytes());
-        result.append(&mut self.body);
+    /// Transforms the whole request into a vector of bytes (Vec<u8>). A
+    /// streaming body is read to the end, or until the reader fails.
+    pub fn byteify(self) -> Vec<u8> {
+        let mut result: Vec<u8> = Vec::new();
+        let _ = self.write_to(&mut result);
         result
     }
 
+    /// Writes the response to `out`, reading a streaming body a piece at a
+    /// time so it never has to be held in memory. A stream of unknown
+    /// length is sent with chunked transfer encoding. Returns the number of
+    /// body bytes written, not counting chunk framing.
+    pub fn write_to<W: Write>(mut self, out: &mut W) -> io::Result<u64> {
+        let head = self.stringify();
+        out.write_all(head.as_bytes())?;
+        let written = match self.stream.take() {
+            None => {
+                out.write_all(&self.body)?;
+                self.body.len() as u64
+            },
+            Some(BodyStream { mut reader, length: Some(length) }) => {
+                let written = io::copy(&mut reader.by_ref().take(length), out)?;
+                if written < length {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "response stream ended before its Content-Length"));
+                }
+                written
+            },
+            Some(BodyStream { mut reader, length: None }) => {
+                let mut buffer = vec![0; 16 * 1024];
+                let mut written = 0;
+                loop {
+                    let read = match reader.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    write!(out, "{:x}\r\n", read)?;
+                    out.write_all(&buffer[..read])?;
+                    out.write_all(b"\r\n")?;
+                    written += read as u64;
+                }
+                out.write_all(b"0\r\n\r\n")?;
+                written
+            },
+        };
+        out.flush()?;
+        Ok(written)
+    }
+
+    /// Reads a streaming body into memory so it can be looked at with
+    /// `get_body`. Responses that aren't streaming are returned as they are.
+    pub fn buffered(mut self) -> io::Result<Response> {
+        if let Some(mut stream) = self.stream.take() {
+            let mut body = Vec::new();
+            match stream.length {
+                Some(length) => stream.reader.take(length).read_to_end(&mut body)?,
+                None => stream.reader.read_to_end(&mut body)?,
+            };
+            self.body = body;
+        }
+        Ok(self)
+    }
+
     pub fn get_status(&self) -> i32 {
         self.status.clone()
     }
@@ -279,14 +860,29 @@ impl Response {
         self.content_type.clone()
     }
 
+    /// Returns the body, which is empty for a streaming response until it
+    /// has been `buffered`.
     pub fn get_body(&self) -> Vec<u8> {
         self.body.clone()
     }
 
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
     pub fn get_headers(&self) -> Option<HashMap<String, String>> {
         self.headers.clone()
     }
 
+    pub fn get_route_pattern(&self) -> Option<String> {
+        self.route_pattern.clone()
+    }
+
+    pub fn with_route_pattern(mut self, pattern: &str) -> Response {
+        self.route_pattern = Some(String::from(pattern));
+        self
+    }
+
     pub fn with_status(mut self, res_status: i32) -> Response {
         self.status = res_status;
         self
@@ -299,6 +895,17 @@ impl Response {
 
     pub fn with_body(mut self, res_body: Vec<u8>) -> Response {
         self.body = res_body;
+        self.stream = None;
+        self
+    }
+
+    /// Replaces the body with one read from `reader` as it is sent to the
+    /// client. `length` is how many bytes to send from the reader, if known,
+    /// otherwise the body is sent with chunked transfer encoding until the
+    /// reader runs out.
+    pub fn with_stream<R: Read + Send + 'static>(mut self, reader: R, length: Option<u64>) -> Response {
+        self.body = Vec::new();
+        self.stream = Some(BodyStream { reader: Box::new(reader), length });
         self
     }
 
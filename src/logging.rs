@@ -0,0 +1,237 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use http::date::DateTime;
+
+/// How serious a log message is, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn stringify(&self) -> &'static str {
+        match *self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.stringify())
+    }
+}
+
+/// A single log message along with any structured fields describing it,
+/// such as the file that couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    level: Level,
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn new(level: Level, message: &str) -> Record {
+        Record {
+            level,
+            message: String::from(message),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_field(mut self, key: &str, value: &str) -> Record {
+        self.fields.push((String::from(key), String::from(value)));
+        self
+    }
+
+    pub fn get_level(&self) -> Level {
+        self.level
+    }
+
+    pub fn get_message(&self) -> String {
+        self.message.clone()
+    }
+
+    pub fn get_fields(&self) -> Vec<(String, String)> {
+        self.fields.clone()
+    }
+
+    /// Returns the value of the first field with the given key.
+    pub fn get_field(&self, key: &str) -> Option<String> {
+        self.fields.iter()
+            .find(|field| field.0 == key)
+            .map(|field| field.1.clone())
+    }
+}
+
+/// Formats a record as `LEVEL message key=value ...`, quoting any value
+/// that contains spaces, quotes or `=`.
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.level, self.message)?;
+        for (key, value) in &self.fields {
+            let plain = !value.is_empty() && !value.chars().any(|c| c.is_whitespace() || c == '"' || c == '=');
+            if plain {
+                write!(f, " {}={}", key, value)?;
+            } else {
+                write!(f, " {}={:?}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives the diagnostics Servo writes while serving requests, such as
+/// files that couldn't be found or connections that dropped. Set one on
+/// the Configuration with `Configuration::with_logger` to send them to your
+/// own log pipeline. `enabled` lets a Logger skip building records for
+/// levels it would ignore anyway.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record);
+
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+}
+
+/// The default Logger, which writes records at or above a level to stderr
+/// with a timestamp, such as
+/// `2000-10-10T13:55:36Z WARN Could not find file to serve file=static/a.css`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StderrLogger {
+    level: Level,
+}
+
+impl StderrLogger {
+    /// Creates a logger that writes Info records and above.
+    pub fn new() -> StderrLogger {
+        StderrLogger {
+            level: Level::Info,
+        }
+    }
+
+    pub fn with_level(mut self, level: Level) -> StderrLogger {
+        self.level = level;
+        self
+    }
+
+    pub fn get_level(&self) -> Level {
+        self.level
+    }
+}
+
+impl Default for StderrLogger {
+    fn default() -> StderrLogger {
+        StderrLogger::new()
+    }
+}
+
+impl Logger for StderrLogger {
+    fn log(&self, record: &Record) {
+        let stderr = io::stderr();
+        let mut handle = stderr.lock();
+        let _ = writeln!(handle, "{} {}", DateTime::now().to_iso8601(), record);
+    }
+
+    fn enabled(&self, level: Level) -> bool {
+        level >= self.level
+    }
+}
+
+/// A Logger that keeps every record in memory so tests can check what was
+/// logged. Clones share the same records.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureLogger {
+    records: Arc<Mutex<Vec<Record>>>,
+}
+
+impl CaptureLogger {
+    pub fn new() -> CaptureLogger {
+        CaptureLogger::default()
+    }
+
+    /// Returns every record logged so far, oldest first.
+    pub fn records(&self) -> Vec<Record> {
+        match self.records.lock() {
+            Ok(records) => records.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn clear(&self) {
+        match self.records.lock() {
+            Ok(mut records) => records.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+    }
+}
+
+impl Logger for CaptureLogger {
+    fn log(&self, record: &Record) {
+        match self.records.lock() {
+            Ok(mut records) => records.push(record.clone()),
+            Err(poisoned) => poisoned.into_inner().push(record.clone()),
+        }
+    }
+}
+
+#[test]
+fn test_record_display() {
+    let record = Record::new(Level::Warn, "Could not find file to serve")
+        .with_field("file", "static/a.css")
+        .with_field("error", "No such file (os error 2)")
+        .with_field("empty", "");
+    assert_eq!(record.to_string(),
+               "WARN Could not find file to serve file=static/a.css error=\"No such file (os error 2)\" empty=\"\"");
+    assert_eq!(record.get_field("error"), Some(String::from("No such file (os error 2)")));
+    assert!(StderrLogger::new().enabled(Level::Error));
+    assert!(!StderrLogger::new().with_level(Level::Error).enabled(Level::Warn));
+}
+
+#[test]
+fn test_capture_logger_receives_diagnostics() {
+    use super::{Configuration, Routes, Server, get_html, route_request};
+    use http::Request;
+    struct ErrorsOnly(CaptureLogger);
+    impl Logger for ErrorsOnly {
+        fn log(&self, record: &Record) {
+            self.0.log(record)
+        }
+        fn enabled(&self, level: Level) -> bool {
+            level >= Level::Error
+        }
+    }
+    let logger = CaptureLogger::new();
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir("no_such_dir/").with_html_dir("no_such_dir/"))
+        .with_routes(Routes::new().with_route("GET /boom", |_: Request, _: &Configuration| -> &'static str { panic!("boom") }))
+        .with_logger(logger.clone());
+    assert_eq!(get_html("index.html", &configs), "");
+    route_request(Request::from("GET /static/app.css HTTP/1.1\r\n\r\n"), &configs);
+    route_request(Request::from("GET /boom HTTP/1.1\r\n\r\n"), &configs);
+    let records = logger.records();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].get_message(), "Could not open HTML file");
+    assert_eq!(records[0].get_field("file"), Some(String::from("no_such_dir/index.html")));
+    assert_eq!(records[1].get_level(), Level::Warn);
+    assert_eq!(records[1].get_field("file"), Some(String::from("no_such_dir/app.css")));
+    assert_eq!(records[2].get_level(), Level::Error);
+    assert_eq!(records[2].get_field("route"), Some(String::from("GET /boom")));
+    assert_eq!(records[2].get_field("panic"), Some(String::from("boom")));
+
+    logger.clear();
+    let quiet = configs.with_logger(ErrorsOnly(logger.clone()));
+    route_request(Request::from("GET /static/app.css HTTP/1.1\r\n\r\n"), &quiet);
+    assert!(logger.records().is_empty());
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use http::StatusCode;
+
+/// The methods counted under their own name. Any other method is counted
+/// as `OTHER`, so clients making up methods can't add series without end.
+pub const KNOWN_METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS", "CONNECT", "TRACE"];
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counts what the server is doing: requests by route pattern, method and
+/// status, how long they took, how many connections are open or waiting
+/// for a thread, and how many requests panicked. Every Configuration has
+/// one, and clones share the same counts. `render` writes them in the
+/// Prometheus text exposition format.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    open_connections: Arc<AtomicUsize>,
+    queued_connections: Arc<AtomicUsize>,
+    panics: Arc<AtomicUsize>,
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, StatusCode), u64>,
+    latencies: BTreeMap<(String, String), Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; 11],
+    sum: f64,
+    count: u64,
+}
+
+/// Keeps a gauge raised for as long as it is alive.
+pub struct GaugeGuard {
+    gauge: Arc<AtomicUsize>,
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.gauge.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Records an answered request. `route` is the pattern of the route
+    /// that answered it, such as `/users/{}`, so that requests for different
+    /// users are counted together. Methods outside `KNOWN_METHODS` are
+    /// counted as `OTHER`.
+    pub fn observe(&self, route: &str, method: &str, status: StatusCode, latency: Duration) {
+        let method = method_label(method);
+        let seconds = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+        let mut registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *registry.requests
+            .entry((String::from(route), String::from(method), status))
+            .or_insert(0) += 1;
+        let histogram = registry.latencies
+            .entry((String::from(route), String::from(method)))
+            .or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Returns how many requests for a route and method got the given status.
+    pub fn request_count(&self, route: &str, method: &str, status: StatusCode) -> u64 {
+        let method = method_label(method);
+        let registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        registry.requests
+            .get(&(String::from(route), String::from(method), status))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Counts a connection as open until the returned guard is dropped.
+    pub fn track_connection(&self) -> GaugeGuard {
+        self.open_connections.fetch_add(1, Ordering::SeqCst);
+        GaugeGuard { gauge: self.open_connections.clone() }
+    }
+
+    /// Counts a connection as waiting for a worker until the returned guard
+    /// is dropped.
+    pub fn track_queued(&self) -> GaugeGuard {
+        self.queued_connections.fetch_add(1, Ordering::SeqCst);
+        GaugeGuard { gauge: self.queued_connections.clone() }
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::SeqCst)
+    }
+
+    pub fn queued_connections(&self) -> usize {
+        self.queued_connections.load(Ordering::SeqCst)
+    }
+
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn panic_count(&self) -> usize {
+        self.panics.load(Ordering::SeqCst)
+    }
+
+    /// Writes every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut out = String::new();
+        out.push_str("# HELP servo_requests_total Requests answered, by route pattern, method and status.\n");
+        out.push_str("# TYPE servo_requests_total counter\n");
+        for ((route, method, status), count) in &registry.requests {
+            let _ = writeln!(out, "servo_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                             escape_label(route), escape_label(method), status, count);
+        }
+        out.push_str("# HELP servo_request_duration_seconds Time taken to answer requests, by route pattern and method.\n");
+        out.push_str("# TYPE servo_request_duration_seconds histogram\n");
+        for ((route, method), histogram) in &registry.latencies {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape_label(route), escape_label(method));
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(out, "servo_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative);
+            }
+            let _ = writeln!(out, "servo_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(out, "servo_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(out, "servo_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+        out.push_str("# HELP servo_open_connections Connections currently being handled.\n");
+        out.push_str("# TYPE servo_open_connections gauge\n");
+        let _ = writeln!(out, "servo_open_connections {}", self.open_connections());
+        out.push_str("# HELP servo_queued_connections Connections accepted but not yet picked up by a worker.\n");
+        out.push_str("# TYPE servo_queued_connections gauge\n");
+        let _ = writeln!(out, "servo_queued_connections {}", self.queued_connections());
+        out.push_str("# HELP servo_panics_total Requests whose handler or middleware panicked.\n");
+        out.push_str("# TYPE servo_panics_total counter\n");
+        let _ = writeln!(out, "servo_panics_total {}", self.panic_count());
+        out
+    }
+}
+
+/// Returns the label a request method is counted under.
+fn method_label(method: &str) -> &str {
+    if KNOWN_METHODS.contains(&method) { method } else { "OTHER" }
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[test]
+fn test_metrics_render() {
+    let metrics = Metrics::new();
+    metrics.observe("/users/{}", "GET", 200, Duration::from_millis(20));
+    metrics.observe("/users/{}", "GET", 200, Duration::from_secs(30));
+    metrics.observe("/say \"hi\"", "POST", 500, Duration::from_millis(1));
+    let open = metrics.track_connection();
+    let _queued = metrics.track_queued();
+    let _second = metrics.track_connection();
+    drop(open);
+    metrics.record_panic();
+    let text = metrics.render();
+    assert!(text.contains("\nservo_requests_total{route=\"/users/{}\",method=\"GET\",status=\"200\"} 2\n"));
+    assert!(text.contains("\nservo_requests_total{route=\"/say \\\"hi\\\"\",method=\"POST\",status=\"500\"} 1\n"));
+    assert!(text.contains("\nservo_request_duration_seconds_bucket{route=\"/users/{}\",method=\"GET\",le=\"0.01\"} 0\n"));
+    assert!(text.contains("\nservo_request_duration_seconds_bucket{route=\"/users/{}\",method=\"GET\",le=\"0.025\"} 1\n"));
+    assert!(text.contains("\nservo_request_duration_seconds_bucket{route=\"/users/{}\",method=\"GET\",le=\"10\"} 1\n"));
+    assert!(text.contains("\nservo_request_duration_seconds_bucket{route=\"/users/{}\",method=\"GET\",le=\"+Inf\"} 2\n"));
+    assert!(text.contains("\nservo_request_duration_seconds_sum{route=\"/users/{}\",method=\"GET\"} 30.02\n"));
+    assert!(text.contains("\nservo_open_connections 1\n"));
+    assert!(text.contains("\nservo_queued_connections 1\n"));
+    assert!(text.contains("\nservo_panics_total 1\n"));
+}
+
+#[test]
+fn test_metrics_count_route_patterns() {
+    use super::{Configuration, Routes, route_request};
+    use http::Request;
+    let get = |route: &str| Request::new().with_method(String::from("GET")).with_route(String::from(route));
+    let configs = Configuration::new()
+        .with_routes(Routes::new()
+            .with_route("GET /users/{}", |req: Request, _: &Configuration| req.get_url_args())
+            .with_mount("/billing", Routes::new().with_route("GET /invoices", |_, _| "invoices")))
+        .with_metrics_route("/metrics");
+    route_request(get("/users/1"), &configs);
+    route_request(get("/users/2?tab=posts"), &configs);
+    route_request(get("/billing/invoices"), &configs);
+    route_request(get("/nowhere"), &configs);
+    let metrics = configs.get_metrics();
+    assert_eq!(metrics.request_count("/users/{}", "GET", 200), 2);
+    assert_eq!(metrics.request_count("/billing/invoices", "GET", 200), 1);
+    assert_eq!(metrics.request_count("unmatched", "GET", 404), 1);
+    let resp = route_request(get("/metrics"), &configs);
+    assert_eq!(resp.get_status(), 200);
+    let body = String::from_utf8(resp.get_body()).unwrap();
+    assert!(body.contains("servo_requests_total{route=\"unmatched\",method=\"GET\",status=\"404\"} 1"));
+    assert_eq!(metrics.request_count("/metrics", "GET", 200), 1);
+}
+
+#[test]
+fn test_metrics_bound_method_labels() {
+    use super::{Configuration, route_request};
+    use http::Request;
+    let configs = Configuration::new();
+    for method in &["FOO", "BAR", "get", "X-Made-Up"] {
+        route_request(Request::new().with_method(String::from(*method)).with_route(String::from("/nowhere")), &configs);
+    }
+    route_request(Request::new().with_method(String::from("DELETE")).with_route(String::from("/nowhere")), &configs);
+    let metrics = configs.get_metrics();
+    assert_eq!(metrics.request_count("unmatched", "OTHER", 404), 4);
+    assert_eq!(metrics.request_count("unmatched", "DELETE", 404), 1);
+    let text = metrics.render();
+    assert!(text.contains("servo_requests_total{route=\"unmatched\",method=\"OTHER\",status=\"404\"} 4\n"));
+    assert!(!text.contains("FOO") && !text.contains("X-Made-Up"));
+}
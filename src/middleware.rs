@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use super::{Configuration, Handler};
+use http::{Request, Response};
+
+/// Hooks that run around a route's CallBack. `before` is given the
+/// Request before it reaches the CallBack and may modify it or answer
+/// the request itself by returning `Some(response)`. `after` is given
+/// the Response on its way back out and returns the Response that
+/// should be sent instead. Both methods default to doing nothing so
+/// implementors only need to write the hooks they care about.
+pub trait Middleware: Send + Sync {
+    fn before(&self, _request: &mut Request, _configs: &Configuration) -> Option<Response> {
+        None
+    }
+
+    fn after(&self, _request: &Request, response: Response, _configs: &Configuration) -> Response {
+        response
+    }
+}
+
+/// Runs a Request through a middleware stack and the given handler.
+/// `before` hooks run in the order the middleware was added and `after`
+/// hooks run in reverse order. If a `before` hook returns a Response, the
+/// remaining `before` hooks and the handler are skipped, but the `after`
+/// hooks of every middleware that has already run (including the one that
+/// answered) still see the Response.
+pub fn run_middleware(middleware: &[Arc<dyn Middleware>],
+                      mut request: Request,
+                      configs: &Configuration,
+                      handler: &dyn Handler) -> Response {
+    if middleware.is_empty() {
+        return handler.call(request, configs);
+    }
+    let mut entered = 0;
+    let mut answered = None;
+    for layer in middleware {
+        entered += 1;
+        answered = layer.before(&mut request, configs);
+        if answered.is_some() {
+            break;
+        }
+    }
+    let mut response = match answered {
+        Some(response) => response,
+        None => handler.call(request.clone(), configs),
+    };
+    for layer in middleware[..entered].iter().rev() {
+        response = layer.after(&request, response, configs);
+    }
+    response
+}
@@ -3,17 +3,41 @@
 
 #![allow(dead_code)]
 
+pub mod access_log;
+pub mod compression;
+pub mod errors;
+pub mod extract;
+pub mod health;
 pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
+pub mod static_files;
+pub mod vfs;
 
-use self::http::{Request, Response};
-use self::http::content_type::{ContentType, get_content_type};
-use std::collections::BTreeMap;
-use std::fs::File;
+use self::access_log::{AccessLog, AccessLogEntry};
+use self::health::HealthChecks;
+use self::errors::{ErrorHandler, ErrorPage, default_error_response};
+use self::http::{HttpError, IntoResponse, Request, Response, StatusCode};
+use self::logging::{Level, Logger, Record, StderrLogger};
+use self::metrics::{GaugeGuard, Metrics};
+use self::middleware::{Middleware, run_middleware};
+use self::static_files::{SpaFallback, StaticMount, StaticOptions, spa_fallback};
+use self::vfs::{DirectoryFiles, FileSystem};
+use self::http::content_type::ContentType;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
 use std::io::Read;
-use std::net::{TcpStream, TcpListener};
-use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, TcpListener};
+use std::io::BufWriter;
+use std::panic::{self, AssertUnwindSafe};
 use std::str;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 
 /// Function type that all routes must map to.
 /// Responses can be built using the built-in
@@ -22,6 +46,22 @@ use std::thread;
 /// enum specifier (servo::http::CONTENT_TYPE).
 pub type CallBack = fn(Request, &Configuration) -> Response;
 
+/// Anything that can answer a routed Request. This is implemented for every
+/// `Fn(Request, &Configuration) -> R` that is `Send + Sync` and returns
+/// something implementing `http::IntoResponse`, which covers plain CallBack
+/// functions as well as closures that capture values such as a database pool,
+/// and handlers returning a `Result`, a String or JSON. Routes hold their
+/// handlers behind an `Arc` so the route map stays cheap to clone for each
+/// request.
+pub trait Handler: Send + Sync {
+    fn call(&self, request: Request, configs: &Configuration) -> Response;
+}
+
+impl<F, R> Handler for F where F: Fn(Request, &Configuration) -> R + Send + Sync, R: IntoResponse {
+    fn call(&self, request: Request, configs: &Configuration) -> Response {
+        self(request, configs).into_response()
+    }
+}
 
 /// Function type of the routing function for
 /// Servo. This allows anybody to inject their own
@@ -31,6 +71,31 @@ pub type CallBack = fn(Request, &Configuration) -> Response;
 /// that will be attached to the Request given to the CallBack.
 pub type Router = fn(&Request, &Routes) -> (Vec<String>, CallBack);
 
+/// Function type of a routing function that can pick any Handler, such as
+/// a closure route from the route map, rather than only a CallBack. Returns
+/// None if no route matches, so Servo can answer with a 405 or 404. Servo's
+/// own routing function is one of these.
+pub type HandlerRouter = fn(&Request, &Routes) -> Option<(Vec<String>, Arc<dyn Handler>)>;
+
+/// The routing function set on a Server or route table, of either kind.
+#[derive(Clone, Copy)]
+enum RoutingFunction {
+    CallBack(Router),
+    Handler(HandlerRouter),
+}
+
+impl RoutingFunction {
+    fn route(self, request: &Request, routes: &Routes) -> Option<(Vec<String>, Arc<dyn Handler>)> {
+        match self {
+            RoutingFunction::CallBack(router) => {
+                let (args, callback) = router(request, routes);
+                Some((args, Arc::new(callback)))
+            },
+            RoutingFunction::Handler(router) => router(request, routes),
+        }
+    }
+}
+
 /// Defines which HTTP protocol to use. Valid values are 
 /// `HttpProtocol::Http` and `HttpProtocol::Https`. HTTPS 
 /// is not currently supported but is included here for future 
@@ -70,34 +135,126 @@ impl Servo {
         self
     }
 
+    /// Returns the handle used to shut this server down. See `ShutdownHandle`.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.configuration.shutdown_handle()
+    }
+
     /// Starts the server listening on the configured host and port. 
     /// The default is `127.0.0.1:8000`. Spins up a new thread to handle 
-    /// each request.
+    /// each request. Returns once the server has been shut down through its
+    /// ShutdownHandle and the requests it was answering have finished.
     pub fn run(&self) {
         let host = self.configuration.server.get_host();
         let port = self.configuration.server.get_port();
         println!("Starting server...");
         let listener = TcpListener::bind(format!("{}:{}", host, port)).unwrap();
         println!("Listening on {}:{}", host, port);
+        let stopped = Arc::new(AtomicBool::new(false));
+        if let Ok(address) = listener.local_addr() {
+            self.stop_after_shutdown(address, stopped.clone());
+        }
 
         for stream in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
             let configs = self.configuration.clone();
             match stream {
                 Ok(stream) => {
+                    let queued = configs.metrics.track_queued();
                     thread::spawn(move || {
-                        handle_request(stream, &configs)
+                        let connection = configs.metrics.track_connection();
+                        drop(queued);
+                        handle_request(stream, &configs, connection)
                     });
                 },
-                Err(e) => eprintln!("Error in handling request: {}", e),
+                Err(e) => self.configuration.log(Level::Error, "Error in handling request", &[("error", &e.to_string())]),
             }
         }
+        println!("Shutting down...");
+        let metrics = self.configuration.get_metrics();
+        let started = Instant::now();
+        let timeout = self.configuration.server.get_drain_timeout();
+        while metrics.open_connections() + metrics.queued_connections() > 0 && started.elapsed() < timeout {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Waits in the background for a shutdown, then for the Server's
+    /// shutdown delay, before waking the accept loop with a connection of
+    /// its own so it sees that it should stop.
+    fn stop_after_shutdown(&self, address: SocketAddr, stopped: Arc<AtomicBool>) {
+        let handle = self.configuration.shutdown_handle();
+        let delay = self.configuration.server.get_shutdown_delay();
+        thread::spawn(move || {
+            handle.wait();
+            thread::sleep(delay);
+            stopped.store(true, Ordering::SeqCst);
+            let wake = match address.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port()),
+                IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port()),
+                _ => address,
+            };
+            let _ = TcpStream::connect(wake);
+        });
+    }
+}
+
+/// Shuts a running server down gracefully. Once `shutdown` is called the
+/// readiness probe reports the server as not ready, and after the Server's
+/// shutdown delay `Servo::run` stops accepting connections, waits for the
+/// requests it is answering to finish and returns. Clones control the same
+/// server, so one can be handed to a signal handler or admin thread before
+/// calling `run`.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    pub fn shutdown(&self) {
+        let (flag, changed) = &*self.state;
+        let mut shutting_down = match flag.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *shutting_down = true;
+        changed.notify_all();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        match self.state.0.lock() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    /// Blocks until `shutdown` is called.
+    pub fn wait(&self) {
+        let (flag, changed) = &*self.state;
+        let mut shutting_down = match flag.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        while !*shutting_down {
+            shutting_down = match changed.wait(shutting_down) {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
     }
 }
 
 /// Holds all configurations for the server itself. Currently 
 /// supports configuration of host, port, static file directory, html file 
 /// directory, base server domain, HTTP protocol and allows the user 
-/// to inject their own routing system into Servo.
+/// to inject their own routing system into Servo. An access log can also
+/// be set to record every request the server answers.
 pub struct Server {
     host : String,
     port : String,
@@ -105,7 +262,16 @@ pub struct Server {
     html_dir : String,
     domain : String,
     protocol : HttpProtocol,
-    router : Router,
+    router : RoutingFunction,
+    access_log : Option<AccessLog>,
+    shutdown_delay : Duration,
+    drain_timeout : Duration,
+    max_request_size : usize,
+    static_options : StaticOptions,
+    static_files : Option<Arc<dyn FileSystem>>,
+    html_files : Option<Arc<dyn FileSystem>>,
+    static_dir_files : Arc<DirectoryFiles>,
+    html_dir_files : Arc<DirectoryFiles>,
 }
 
 impl Server {
@@ -125,7 +291,16 @@ impl Server {
             static_dir : String::from("static/"),
             html_dir : String::from("templates/"),
             protocol : HttpProtocol::Http,
-            router : default_router
+            router : RoutingFunction::Handler(default_router),
+            access_log : None,
+            shutdown_delay : Duration::from_secs(0),
+            drain_timeout : Duration::from_secs(30),
+            max_request_size : DEFAULT_MAX_REQUEST_SIZE,
+            static_options : StaticOptions::default(),
+            static_files : None,
+            html_files : None,
+            static_dir_files : Arc::new(DirectoryFiles::new("static/")),
+            html_dir_files : Arc::new(DirectoryFiles::new("templates/")),
         }
     }
 
@@ -173,31 +348,144 @@ impl Server {
 
     pub fn with_static_dir(mut self, static_dir: &str) -> Server {
         self.static_dir = String::from(static_dir);
+        self.static_dir_files = Arc::new(DirectoryFiles::new(static_dir));
         self
     }
 
     pub fn with_html_dir(mut self, html_dir: &str) -> Server {
         self.html_dir = String::from(html_dir);
+        self.html_dir_files = Arc::new(DirectoryFiles::new(html_dir));
         self
     }
 
     pub fn with_router(mut self, router: Router) -> Server {
-        self.router = router;
+        self.router = RoutingFunction::CallBack(router);
         self
     }
 
-    pub fn route_request(&self, request: &Request, routes: &Routes) -> (Vec<String>, CallBack) {
-        (self.router)(request, &routes)
+    /// Same as `with_router` but for a routing function that can pick any
+    /// Handler. See `HandlerRouter`.
+    pub fn with_handler_router(mut self, router: HandlerRouter) -> Server {
+        self.router = RoutingFunction::Handler(router);
+        self
+    }
+
+    /// Records every request in the given access log, such as
+    /// `AccessLog::file("access.log").with_format(LogFormat::Combined)`.
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Server {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    pub fn get_access_log(&self) -> Option<AccessLog> {
+        self.access_log.clone()
+    }
+
+    /// Sets how long the server keeps accepting connections after a
+    /// shutdown is requested, while the readiness probe reports it as not
+    /// ready, so load balancers can stop sending it traffic first. The
+    /// default is no delay.
+    pub fn with_shutdown_delay(mut self, delay: Duration) -> Server {
+        self.shutdown_delay = delay;
+        self
+    }
+
+    pub fn get_shutdown_delay(&self) -> Duration {
+        self.shutdown_delay
+    }
+
+    /// Sets how long `Servo::run` waits for the requests still being
+    /// answered once it has stopped accepting connections, before returning
+    /// anyway. The default is 30 seconds.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Server {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn get_drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Sets the largest request, headers and body together, the server
+    /// reads, in bytes. Larger requests are answered with a
+    /// `413 Payload Too Large`. The default is `DEFAULT_MAX_REQUEST_SIZE`.
+    pub fn with_max_request_size(mut self, bytes: usize) -> Server {
+        self.max_request_size = bytes;
+        self
+    }
+
+    pub fn get_max_request_size(&self) -> usize {
+        self.max_request_size
+    }
+
+    /// Allows static files to be served through symlinks leading outside
+    /// the static directory, or hidden files to be served. Neither is
+    /// allowed by default.
+    pub fn with_static_options(mut self, options: StaticOptions) -> Server {
+        self.static_options = options;
+        self
+    }
+
+    pub fn get_static_options(&self) -> StaticOptions {
+        self.static_options
+    }
+
+    /// Serves static files from the given `FileSystem` instead of the
+    /// static directory, such as `EmbeddedFiles` compiled into the binary.
+    pub fn with_static_files<F: FileSystem + 'static>(mut self, files: F) -> Server {
+        self.static_files = Some(Arc::new(files));
+        self
+    }
+
+    /// Returns where static files are read from, which is the static
+    /// directory unless `with_static_files` was used.
+    pub fn get_static_files(&self) -> Arc<dyn FileSystem> {
+        match self.static_files {
+            Some(ref files) => files.clone(),
+            None => self.static_dir_files.clone(),
+        }
+    }
+
+    /// Reads HTML files for `get_html` from the given `FileSystem` instead
+    /// of the HTML directory.
+    pub fn with_html_files<F: FileSystem + 'static>(mut self, files: F) -> Server {
+        self.html_files = Some(Arc::new(files));
+        self
+    }
+
+    /// Returns where HTML files are read from, which is the HTML directory
+    /// unless `with_html_files` was used.
+    pub fn get_html_files(&self) -> Arc<dyn FileSystem> {
+        match self.html_files {
+            Some(ref files) => files.clone(),
+            None => self.html_dir_files.clone(),
+        }
+    }
+
+    /// Routes the request using the route table's own Router if it was given
+    /// one, falling back on the Server's Router otherwise.
+    pub fn route_request(&self, request: &Request, routes: &Routes) -> Option<(Vec<String>, Arc<dyn Handler>)> {
+        routes.router.unwrap_or(self.router).route(request, routes)
     }
 
     pub fn clone(&self) -> Server {
-        Server::new()
+        let mut server = Server::new()
             .with_host(&self.host)
             .with_port(&self.port)
             .with_domain(&self.domain)
             .with_static_dir(&self.static_dir)
-            .with_html_dir(&self.html_dir)
-            .with_router(self.router.clone())
+            .with_html_dir(&self.html_dir);
+        server.router = self.router;
+        server.access_log = self.access_log.clone();
+        server.shutdown_delay = self.shutdown_delay;
+        server.drain_timeout = self.drain_timeout;
+        server.max_request_size = self.max_request_size;
+        server.static_options = self.static_options;
+        server.static_files = self.static_files.clone();
+        server.html_files = self.html_files.clone();
+        server.static_dir_files = self.static_dir_files.clone();
+        server.html_dir_files = self.html_dir_files.clone();
+        server
     }
 }
 
@@ -205,31 +493,177 @@ impl Server {
 /// to run or maintain the server but the definition is provided publicly
 /// for reference through the Configuration struct.
 pub struct Routes {
-    pub route_map: BTreeMap<String, CallBack>,
+    pub route_map: BTreeMap<String, Arc<dyn Handler>>,
+    groups: Vec<RouteGroup>,
+    mounts: Vec<Mount>,
+    names: BTreeMap<String, String>,
+    router: Option<RoutingFunction>,
+    static_dir: Option<String>,
+    static_mounts: Vec<StaticMount>,
+    spa_fallback: Option<SpaFallback>,
 }
 
 impl Routes {
     /// Creates a new route map with two default routes:
     /// `GET /` and `GET /static/{}`. The `GET /` method should 
     /// be overwritten with your own, custom homepage. The `GET /static/{}` 
-    /// should NOT be overwritten as this can cause Servo to quit serving static 
-    /// files correctly.
+    /// route belongs to the `static` mount and should NOT be overwritten as
+    /// this can cause Servo to quit serving static files correctly. Move it
+    /// with `with_static_mount` instead.
     pub fn new() -> Routes {
-        let mut map: BTreeMap<String, CallBack> = BTreeMap::new();
-        map.insert(String::from("GET /"), default_home);
-        map.insert(String::from("GET /static/{}"), static_route);
-        Routes {
-            route_map: map
+        let mut map: BTreeMap<String, Arc<dyn Handler>> = BTreeMap::new();
+        map.insert(String::from("GET /"), Arc::new(default_home));
+        let mut routes = Routes {
+            route_map: map,
+            groups: Vec::new(),
+            mounts: Vec::new(),
+            names: BTreeMap::new(),
+            router: None,
+            static_dir: None,
+            static_mounts: Vec::new(),
+            spa_fallback: None,
+        };
+        routes.add_static_mount(StaticMount::new("static", "/static"));
+        routes
+    }
+
+    /// Gives this route table its own routing function, used instead of the
+    /// Server's Router. Mostly useful for route tables that get mounted.
+    pub fn with_router(mut self, router: Router) -> Routes {
+        self.router = Some(RoutingFunction::CallBack(router));
+        self
+    }
+
+    /// Same as `with_router` but for a routing function that can pick any
+    /// Handler. See `HandlerRouter`.
+    pub fn with_handler_router(mut self, router: HandlerRouter) -> Routes {
+        self.router = Some(RoutingFunction::Handler(router));
+        self
+    }
+
+    /// Gives this route table its own static file directory, used instead of
+    /// the Server's static directory. Mostly useful for route tables that get
+    /// mounted.
+    pub fn with_static_dir(mut self, static_dir: &str) -> Routes {
+        self.static_dir = Some(String::from(static_dir));
+        self
+    }
+
+    /// Returns the static directory set on this route table, if any.
+    pub fn get_static_directory(&self) -> Option<String> {
+        self.static_dir.clone()
+    }
+
+    /// Serves files under a URL prefix, as described on `StaticMount`. Used
+    /// in the builder pattern.
+    pub fn with_static_mount(mut self, mount: StaticMount) -> Routes {
+        self.add_static_mount(mount);
+        self
+    }
+
+    /// Serves files under a URL prefix, in place. A mount with the same name
+    /// is replaced, along with its route.
+    pub fn add_static_mount(&mut self, mount: StaticMount) {
+        if let Some(index) = self.static_mounts.iter().position(|existing| existing.get_name() == mount.get_name()) {
+            let replaced = self.static_mounts.remove(index);
+            self.route_map.remove(&replaced.route_key());
+        }
+        let serving = mount.clone();
+        self.route_map.insert(mount.route_key(), Arc::new(move |request: Request, configs: &Configuration| {
+            serving.serve(request, configs)
+        }));
+        self.static_mounts.push(mount);
+    }
+
+    /// Returns the static mount with the given name, if any.
+    pub fn get_static_mount(&self, name: &str) -> Option<&StaticMount> {
+        self.static_mounts.iter().find(|mount| mount.get_name() == name)
+    }
+
+    /// Answers page requests that match no route or static file with a
+    /// single page app's `index.html`, as described on `SpaFallback`. Used
+    /// in the builder pattern.
+    pub fn with_spa_fallback(mut self, fallback: SpaFallback) -> Routes {
+        self.set_spa_fallback(fallback);
+        self
+    }
+
+    /// Sets the SPA fallback in place. See `with_spa_fallback`.
+    pub fn set_spa_fallback(&mut self, fallback: SpaFallback) {
+        self.spa_fallback = Some(fallback);
+    }
+
+    pub fn get_spa_fallback(&self) -> Option<&SpaFallback> {
+        self.spa_fallback.as_ref()
+    }
+
+    /// Returns the URL prefix of the static mount with the given name, in the
+    /// format `/assets`. Mounts in mounted route tables are found as well,
+    /// with the mount prefix attached.
+    pub fn get_static_prefix(&self, name: &str) -> Option<String> {
+        if let Some(mount) = self.get_static_mount(name) {
+            return Some(mount.get_prefix());
         }
+        self.mounts.iter()
+            .filter_map(|mount| mount.routes.get_static_prefix(name)
+                .map(|prefix| format!("{}{}", mount.prefix, prefix)))
+            .next()
     }
 
     /// Adds route/callback function pair to the current route map. Used in the 
     /// builder pattern.
-    pub fn with_route(mut self, route: &str, callback: CallBack) -> Routes {
-        self.route_map.insert(String::from(route), callback);
+    pub fn with_route<F, R>(mut self, route: &str, callback: F) -> Routes
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(String::from(route), Arc::new(callback));
         self
     }
 
+    /// Adds a route and any type implementing Handler to the current route
+    /// map. Closures and functions can be given to `with_route` directly, this
+    /// is for custom Handler types. Used in the builder pattern.
+    pub fn with_handler<H: Handler + 'static>(mut self, route: &str, handler: H) -> Routes {
+        self.add_handler(route, handler);
+        self
+    }
+
+    /// Adds a route and any type implementing Handler to the current route
+    /// map, in place.
+    pub fn add_handler<H: Handler + 'static>(&mut self, route: &str, handler: H) {
+        self.route_map.insert(String::from(route), Arc::new(handler));
+    }
+
+    /// Adds a route/callback function pair under a name that can be used to
+    /// build URLs for it with `Configuration::url_for`. Wildcards in the route
+    /// may be named, as in `GET /users/{id}`, so they can be filled in by name.
+    /// Named wildcards are routed exactly like `{}`. Used in the builder pattern.
+    pub fn with_named_route<F, R>(mut self, name: &str, route: &str, callback: F) -> Routes
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_named_route(name, route, callback);
+        self
+    }
+
+    /// Adds a named route/callback function pair to the current route map, in
+    /// place. Should be used `routes.add_named_route("profile", "GET /users/{id}", profile);`
+    pub fn add_named_route<F, R>(&mut self, name: &str, route: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        let (method, path) = split_route_key(route);
+        self.route_map.insert(format!("{} {}", method, anonymize_wildcards(path)), Arc::new(callback));
+        self.names.insert(String::from(name), String::from(path));
+    }
+
+    /// Returns the path pattern registered under the given route name, in the
+    /// format `/users/{id}`. Names registered in mounted route tables are found
+    /// as well, with the mount prefix attached.
+    pub fn get_named_route(&self, name: &str) -> Option<String> {
+        if let Some(path) = self.names.get(name) {
+            return Some(path.clone());
+        }
+        self.mounts.iter()
+            .filter_map(|mount| mount.routes.get_named_route(name)
+                .map(|path| format!("{}{}", mount.prefix, path)))
+            .next()
+    }
+
     /// Returns true if their is an exact match on the given route in the 
     /// route map. Does not match using configured wildcards.
     pub fn contains_route(&self, route: &str) -> bool {
@@ -238,48 +672,301 @@ impl Routes {
 
     /// Gets the callback function associated with a given route. 
     /// Returns an optional reference. Will return None if not found.
-    pub fn get_route(&self, route: &str) -> Option<&CallBack> {
+    pub fn get_route(&self, route: &str) -> Option<&Arc<dyn Handler>> {
         self.route_map.get(route)
     }
 
     /// Adds a route/callback function pair to the current route map, in place.
-    pub fn add_route(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(String::from(key), callback);
+    pub fn add_route<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(String::from(key), Arc::new(callback));
     }
 
     /// Adds a 'GET' route and callback function pair to the current route map, 
     /// in place. Should be used `routes.add_get("/home", my_home);`
-    pub fn add_get(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(format!("GET {}", key), callback);
+    pub fn add_get<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(format!("GET {}", key), Arc::new(callback));
     }
 
     /// Adds a 'POST' route and callback function pair to the current route map, 
     /// in place. Should be used `routes.add_post("/home", my_home);`
-    pub fn add_post(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(format!("POST {}", key), callback);
+    pub fn add_post<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(format!("POST {}", key), Arc::new(callback));
     }
 
     /// Adds a 'DELETE' route and callback function pair to the current route map, 
     /// in place. Should be used `routes.add_delete("/home", my_home);`
-    pub fn add_delete(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(format!("DELETE {}", key), callback);
+    pub fn add_delete<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(format!("DELETE {}", key), Arc::new(callback));
     }
 
     /// Adds a 'PATCH' route and callback function pair to the current route map, 
     /// in place. Should be used `routes.add_patch("/home", my_home);`
-    pub fn add_patch(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(format!("PATCH {}", key), callback);
+    pub fn add_patch<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(format!("PATCH {}", key), Arc::new(callback));
     }
 
     /// Adds a 'PUT' route and callback function pair to the current route map, 
     /// in place. Should be used `routes.add_put("/home", my_home);`
-    pub fn add_put(&mut self, key: &str, callback: CallBack) {
-        self.route_map.insert(format!("PUT {}", key), callback);
+    pub fn add_put<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.route_map.insert(format!("PUT {}", key), Arc::new(callback));
+    }
+
+    /// Registers a group of routes that share a URL prefix. The closure is
+    /// given the new RouteGroup, whose routes are added to this route map with
+    /// the prefix attached. Any middleware or error handler set on the group
+    /// only applies to requests under the prefix. Should be used like:
+    /// `routes.group("/api/v1", |g| { g.add_get("/users", list_users); });`
+    pub fn group<F>(&mut self, prefix: &str, build: F) where F: FnOnce(&mut RouteGroup) {
+        let mut group = RouteGroup::new(prefix);
+        build(&mut group);
+        for (key, handler) in group.route_map.iter() {
+            self.route_map.insert(key.clone(), handler.clone());
+        }
+        for (name, path) in group.names.iter() {
+            self.names.insert(name.clone(), path.clone());
+        }
+        group.route_map.clear();
+        group.names.clear();
+        self.groups.push(group);
+    }
+
+    /// Same as `group` but used in the builder pattern.
+    pub fn with_group<F>(mut self, prefix: &str, build: F) -> Routes where F: FnOnce(&mut RouteGroup) {
+        self.group(prefix, build);
+        self
+    }
+
+    /// Returns the group with the longest prefix covering the given path, if any.
+    pub fn get_group(&self, path: &str) -> Option<&RouteGroup> {
+        self.groups.iter()
+            .filter(|group| group.covers(path))
+            .max_by_key(|group| group.prefix.len())
+    }
+
+    /// Nests a whole route table under a prefix. Every request under the
+    /// prefix is handed to the mounted routes with the prefix stripped from
+    /// its route, so a table with `GET /invoices` mounted at `/billing` answers
+    /// `GET /billing/invoices`. The mounted table keeps its own Router and
+    /// static directory, if it has them, and the stripped prefix is available
+    /// to its CallBacks through `Request::get_mount_point`. Mounts are checked
+    /// before this table's own routes.
+    pub fn mount(&mut self, prefix: &str, routes: Routes) {
+        self.mounts.push(Mount {
+            prefix: normalize_prefix(prefix),
+            routes,
+        });
+    }
+
+    /// Same as `mount` but used in the builder pattern.
+    pub fn with_mount(mut self, prefix: &str, routes: Routes) -> Routes {
+        self.mount(prefix, routes);
+        self
+    }
+
+    /// Returns the mount with the longest prefix covering the given path, if any.
+    pub fn get_mount(&self, path: &str) -> Option<&Mount> {
+        self.mounts.iter()
+            .filter(|mount| is_under_prefix(path, &mount.prefix))
+            .max_by_key(|mount| mount.prefix.len())
     }
 
     pub fn clone(&self) -> Routes {
         Routes {
-            route_map: self.route_map.clone()
+            route_map: self.route_map.clone(),
+            groups: self.groups.clone(),
+            mounts: self.mounts.clone(),
+            names: self.names.clone(),
+            router: self.router,
+            static_dir: self.static_dir.clone(),
+            static_mounts: self.static_mounts.clone(),
+            spa_fallback: self.spa_fallback.clone(),
+        }
+    }
+}
+
+/// A route table nested under a prefix through `Routes::mount`.
+pub struct Mount {
+    prefix: String,
+    routes: Routes,
+}
+
+impl Mount {
+    /// Returns the prefix in the format `/billing` (no trailing slash)
+    pub fn get_prefix(&self) -> String {
+        self.prefix.clone()
+    }
+
+    pub fn get_routes(&self) -> &Routes {
+        &self.routes
+    }
+}
+
+impl Clone for Mount {
+    fn clone(&self) -> Mount {
+        Mount {
+            prefix: self.prefix.clone(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+/// Puts a URL prefix in the format `/api/v1`: a leading slash and no
+/// trailing slash. The root prefix becomes an empty string.
+fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Splits a route key such as `GET /home` into its method and path.
+fn split_route_key(key: &str) -> (&str, &str) {
+    let mut parts = key.splitn(2, ' ');
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("/");
+    (method, path)
+}
+
+/// Turns named wildcards such as `{id}` into the `{}` wildcards used for routing.
+fn anonymize_wildcards(path: &str) -> String {
+    let mut result = String::new();
+    let mut in_wildcard = false;
+    for c in path.chars() {
+        match c {
+            '{' => {
+                in_wildcard = true;
+                result.push_str("{}");
+            },
+            '}' if in_wildcard => in_wildcard = false,
+            _ if in_wildcard => (),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns true if the path is the prefix itself or lies below it.
+fn is_under_prefix(path: &str, prefix: &str) -> bool {
+    path == prefix || path.starts_with(&format!("{}/", prefix))
+}
+
+/// A set of routes sharing a URL prefix, built through `Routes::group`.
+/// Routes are given relative to the prefix, so adding `GET /users` to the
+/// group `/api/v1` registers `GET /api/v1/users`. A group can carry its own
+/// middleware stack, which runs around every request under the prefix, and
+/// its own error handler, which answers requests under the prefix that
+/// don't match any route.
+#[derive(Clone)]
+pub struct RouteGroup {
+    prefix: String,
+    route_map: BTreeMap<String, Arc<dyn Handler>>,
+    names: BTreeMap<String, String>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    error_handler: Option<Arc<dyn Handler>>,
+}
+
+impl RouteGroup {
+    fn new(prefix: &str) -> RouteGroup {
+        RouteGroup {
+            prefix: normalize_prefix(prefix),
+            route_map: BTreeMap::new(),
+            names: BTreeMap::new(),
+            middleware: Vec::new(),
+            error_handler: None,
+        }
+    }
+
+    /// Returns the prefix in the format `/api/v1` (no trailing slash)
+    pub fn get_prefix(&self) -> String {
+        self.prefix.clone()
+    }
+
+    /// Returns true if the given path (without method) falls under this group.
+    pub fn covers(&self, path: &str) -> bool {
+        is_under_prefix(path, &self.prefix)
+    }
+
+    /// Adds a route/callback function pair to the group. The key is given
+    /// as `METHOD /path` and the prefix is inserted before the path.
+    pub fn add_route<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        let (method, path) = split_route_key(key);
+        self.route_map.insert(format!("{} {}", method, self.prefixed(path)), Arc::new(callback));
+    }
+
+    /// Adds a named route/callback function pair to the group. The name can
+    /// be used to build URLs with `Configuration::url_for`, which will include
+    /// the group prefix.
+    pub fn add_named_route<F, R>(&mut self, name: &str, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        let (method, path) = split_route_key(key);
+        let path = self.prefixed(path);
+        self.route_map.insert(format!("{} {}", method, anonymize_wildcards(&path)), Arc::new(callback));
+        self.names.insert(String::from(name), path);
+    }
+
+    /// Adds a 'GET' route and callback function pair to the group.
+    pub fn add_get<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_route(&format!("GET {}", key), callback);
+    }
+
+    /// Adds a 'POST' route and callback function pair to the group.
+    pub fn add_post<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_route(&format!("POST {}", key), callback);
+    }
+
+    /// Adds a 'DELETE' route and callback function pair to the group.
+    pub fn add_delete<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_route(&format!("DELETE {}", key), callback);
+    }
+
+    /// Adds a 'PATCH' route and callback function pair to the group.
+    pub fn add_patch<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_route(&format!("PATCH {}", key), callback);
+    }
+
+    /// Adds a 'PUT' route and callback function pair to the group.
+    pub fn add_put<F, R>(&mut self, key: &str, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_route(&format!("PUT {}", key), callback);
+    }
+
+    /// Adds middleware to the end of the group's middleware stack.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Sets the CallBack used for requests under the group's prefix that
+    /// don't match any route. Requests for a route that exists for other
+    /// methods still get a 405 with an `Allow` header, and page requests
+    /// go to the route table's SPA fallback first if it has one. For the
+    /// rest, this CallBack replaces the Configuration's 404 error handler
+    /// or error page under the prefix. Without one, the 404 is built by
+    /// `Configuration::error_response` like any other.
+    pub fn set_error_handler<F, R>(&mut self, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.error_handler = Some(Arc::new(callback));
+    }
+
+    fn prefixed(&self, path: &str) -> String {
+        if path == "/" || path.is_empty() {
+            if self.prefix.is_empty() { String::from("/") } else { self.prefix.clone() }
+        } else if path.starts_with('/') {
+            format!("{}{}", self.prefix, path)
+        } else {
+            format!("{}/{}", self.prefix, path)
         }
     }
 }
@@ -287,10 +974,26 @@ impl Routes {
 /// Holds server configuration information via the Server and 
 /// Routes structs where Server has string configuration variables and 
 /// Routes holds the callback functions associated with any configured 
-/// routes.
+/// routes. Additional Routes can be kept for virtual hosts, in which case
+/// `routes` answers any host that none of them match. Application state,
+/// such as a database pool, is kept here too so every handler can reach it,
+/// along with the middleware that runs around every request, the handlers
+/// for error responses and the Logger that Servo's diagnostics go to. The
+/// Metrics are shared by every copy of a Configuration.
 pub struct Configuration {
     pub server: Server,
     pub routes: Routes,
+    url_routes: Option<Routes>,
+    hosts: Vec<VirtualHost>,
+    state: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    panic_handler: Option<Arc<dyn Handler>>,
+    metrics: Metrics,
+    metrics_path: Option<String>,
+    health_checks: Option<HealthChecks>,
+    shutdown: ShutdownHandle,
+    error_handlers: BTreeMap<StatusCode, Arc<dyn ErrorHandler>>,
+    logger: Arc<dyn Logger>,
 }
 
 /// This struct contains all of the configuration setup variables
@@ -304,6 +1007,17 @@ impl Configuration {
         let config = Configuration {
             server: Server::new(),
             routes: Routes::new(),
+            url_routes: None,
+            hosts: Vec::new(),
+            state: HashMap::new(),
+            middleware: Vec::new(),
+            panic_handler: None,
+            metrics: Metrics::new(),
+            metrics_path: None,
+            health_checks: None,
+            shutdown: ShutdownHandle::new(),
+            error_handlers: BTreeMap::new(),
+            logger: Arc::new(StderrLogger::new()),
         };
         config
     }
@@ -318,57 +1032,372 @@ impl Configuration {
         self
     }
 
-    /// Returns a String with the fully-qualified static file URI.
-    pub fn get_static_uri(&self) -> String {
-        format!("{}://{}:{}/static/", 
-            self.server.get_protocol().stringify(), 
-            self.server.get_domain(), 
+    /// Adds a route table that answers requests whose `Host` header matches
+    /// the given pattern. Patterns are either exact host names, such as
+    /// `api.example.com`, or wildcards covering every subdomain, such as
+    /// `*.example.com`. Exact patterns win over wildcards and longer wildcards
+    /// win over shorter ones. Requests for any other host are answered by the
+    /// default routes. Used in the builder pattern.
+    pub fn with_host_routes(mut self, pattern: &str, routes: Routes) -> Configuration {
+        self.add_host_routes(pattern, routes);
+        self
+    }
+
+    /// Adds a route table for a host pattern, in place. See `with_host_routes`.
+    pub fn add_host_routes(&mut self, pattern: &str, routes: Routes) {
+        self.hosts.push(VirtualHost {
+            pattern: pattern.trim().to_lowercase(),
+            routes,
+        });
+    }
+
+    /// Stores a value as application state, replacing any earlier value of
+    /// the same type. Handlers get it back with `configs.state::<T>()`. The
+    /// value is shared between all requests, so anything that needs to change
+    /// should be wrapped in a `Mutex` or similar. Used in the builder pattern.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, value: T) -> Configuration {
+        self.add_state(value);
+        self
+    }
+
+    /// Stores a value as application state, in place. See `with_state`.
+    pub fn add_state<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.state.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Adds middleware that runs around every request, whichever host, mount
+    /// or group it is routed to. Middleware runs in the order it was added:
+    /// `before` hooks first to last and `after` hooks last to first. A
+    /// `before` hook that returns a Response stops the request there.
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Configuration {
+        self.add_middleware(middleware);
+        self
+    }
+
+    /// Adds middleware that runs around every request, in place. See
+    /// `with_middleware`.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Sets the handler that builds the 500 Response sent when a CallBack or
+    /// middleware panics. It is given the Request as it was received. If none
+    /// is set, the error handler for 500 is used, and if that panics too a
+    /// plain "Internal server error." page is sent instead.
+    pub fn with_panic_handler<F, R>(mut self, callback: F) -> Configuration
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.set_panic_handler(callback);
+        self
+    }
+
+    /// Sets the panic handler in place. See `with_panic_handler`.
+    pub fn set_panic_handler<F, R>(&mut self, callback: F)
+        where F: Fn(Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.panic_handler = Some(Arc::new(callback));
+    }
+
+    /// Sets the handler that builds error responses with the given status,
+    /// such as 404 for requests that match no route or file, 405 for routes
+    /// that exist for other methods, 400 for requests that can't be parsed
+    /// and 500 for panics. The Response it returns is sent with that status.
+    pub fn with_error_handler<F, R>(mut self, status: StatusCode, callback: F) -> Configuration
+        where F: Fn(&HttpError, &Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.add_error_handler(status, callback);
+        self
+    }
+
+    /// Sets the error handler for a status in place. See `with_error_handler`.
+    pub fn add_error_handler<F, R>(&mut self, status: StatusCode, callback: F)
+        where F: Fn(&HttpError, &Request, &Configuration) -> R + Send + Sync + 'static, R: IntoResponse {
+        self.error_handlers.insert(status, Arc::new(callback));
+    }
+
+    /// Renders error responses with the given status from a template in the
+    /// HTML directory. See `errors::ErrorPage` for what the template may use.
+    pub fn with_error_page(mut self, status: StatusCode, template: &str) -> Configuration {
+        self.add_error_page(status, template);
+        self
+    }
+
+    /// Sets the error page for a status in place. See `with_error_page`.
+    pub fn add_error_page(&mut self, status: StatusCode, template: &str) {
+        self.error_handlers.insert(status, Arc::new(ErrorPage::new(template)));
+    }
+
+    /// Builds the Response for an error with the given status, using the
+    /// error handler registered for it or a default page otherwise. Clients
+    /// that prefer JSON are sent the default page as JSON.
+    pub fn error_response(&self, status: StatusCode, message: &str, request: &Request) -> Response {
+        let error = HttpError::new(status, message);
+        match self.error_handlers.get(&status) {
+            Some(handler) => handler.call(&error, request, self).with_status(status),
+            None => default_error_response(&error, request),
+        }
+    }
+
+    /// Sends Servo's diagnostics, such as missing files and dropped
+    /// connections, to the given Logger instead of stderr.
+    pub fn with_logger<L: Logger + 'static>(mut self, logger: L) -> Configuration {
+        self.set_logger(logger);
+        self
+    }
+
+    /// Sets the Logger in place. See `with_logger`.
+    pub fn set_logger<L: Logger + 'static>(&mut self, logger: L) {
+        self.logger = Arc::new(logger);
+    }
+
+    pub fn get_logger(&self) -> Arc<dyn Logger> {
+        self.logger.clone()
+    }
+
+    /// Logs a message with structured fields, such as
+    /// `configs.log(Level::Warn, "Could not find file to serve", &[("file", &filename)])`.
+    pub fn log(&self, level: Level, message: &str, fields: &[(&str, &str)]) {
+        if !self.logger.enabled(level) {
+            return;
+        }
+        let record = fields.iter()
+            .fold(Record::new(level, message), |record, &(key, value)| record.with_field(key, value));
+        self.logger.log(&record);
+    }
+
+    /// Returns how many requests have panicked since the server started.
+    pub fn panic_count(&self) -> usize {
+        self.metrics.panic_count()
+    }
+
+    /// Serves the server's metrics in the Prometheus text format at the
+    /// given path, such as `/metrics`, on every host. Configuration
+    /// middleware runs for it like any other route.
+    pub fn with_metrics_route(mut self, path: &str) -> Configuration {
+        self.set_metrics_route(path);
+        self
+    }
+
+    /// Sets the metrics route in place. See `with_metrics_route`.
+    pub fn set_metrics_route(&mut self, path: &str) {
+        self.metrics_path = Some(String::from(path));
+    }
+
+    /// Returns the Metrics this server records into.
+    pub fn get_metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Serves a liveness probe at `GET /healthz` and a readiness probe at
+    /// `GET /readyz` on every host, which run the given checks and report
+    /// them as JSON. See `health::HealthChecks`.
+    pub fn with_health_checks(mut self, checks: HealthChecks) -> Configuration {
+        self.set_health_checks(checks);
+        self
+    }
+
+    /// Sets the health checks in place. See `with_health_checks`.
+    pub fn set_health_checks(&mut self, checks: HealthChecks) {
+        self.health_checks = Some(checks);
+    }
+
+    /// Returns the handle used to shut down a server running with this
+    /// Configuration.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns the application state of the given type, such as
+    /// `configs.state::<MyDb>()`, or None if no value of that type was stored.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    /// Same as `state` but returns a shared handle to the value, which can
+    /// be kept beyond the lifetime of the Configuration.
+    pub fn shared_state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state.get(&TypeId::of::<T>())
+            .and_then(|value| value.clone().downcast::<T>().ok())
+    }
+
+    /// Returns the route table for the given host name (lowercase, no port),
+    /// or the default routes if no host pattern matches it.
+    pub fn get_host_routes(&self, host: &str) -> &Routes {
+        match self.find_host(host) {
+            Some(vhost) => &vhost.routes,
+            None => &self.routes,
+        }
+    }
+
+    fn find_host(&self, host: &str) -> Option<&VirtualHost> {
+        let exact = self.hosts.iter().find(|vhost| vhost.pattern == host);
+        let wildcard = || self.hosts.iter()
+            .filter(|vhost| vhost.matches(host))
+            .max_by_key(|vhost| vhost.pattern.len());
+        exact.or_else(wildcard)
+    }
+
+    /// Returns a String with the protocol, domain and port of the server in
+    /// the format `http://my_domain.com:8000` (no trailing slash).
+    pub fn get_base_uri(&self) -> String {
+        format!("{}://{}:{}",
+            self.server.get_protocol().stringify(),
+            self.server.get_domain(),
             self.server.get_port())
     }
 
+    /// Returns a String with the fully-qualified URI of the default `static`
+    /// mount, such as `http://my_domain.com:8000/static/`.
+    pub fn get_static_uri(&self) -> String {
+        self.get_static_uri_for("static")
+            .unwrap_or_else(|| format!("{}/static/", self.get_base_uri()))
+    }
+
+    /// Returns the fully-qualified URI of the static mount with the given
+    /// name, with a trailing slash so file paths can be appended, such as
+    /// `http://my_domain.com:8000/assets/`. None if there is no such mount.
+    pub fn get_static_uri_for(&self, name: &str) -> Option<String> {
+        self.routes.get_static_prefix(name)
+            .map(|prefix| format!("{}{}/", self.get_base_uri(), prefix))
+    }
+
+    /// Builds the path for the route registered under the given name, filling
+    /// its named wildcards from the given parameters, such as
+    /// `configs.url_for("user_profile", &[("id", "42")])` giving `/users/42`.
+    /// Parameters that don't match a wildcard are added as query parameters.
+    /// Values are percent-encoded, except that slashes are kept so a trailing
+    /// wildcard can be filled with several segments. Inside a mounted
+    /// CallBack, names are still looked up in the host's route table, so
+    /// the path includes the mount prefix.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let routes = self.url_routes.as_ref().unwrap_or(&self.routes);
+        let pattern = match routes.get_named_route(name) {
+            Some(pattern) => pattern,
+            None => return Err(UrlError::UnknownRoute(String::from(name))),
+        };
+        let mut url = String::new();
+        let mut used: Vec<&str> = Vec::new();
+        let mut rest = pattern.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let wildcard = &rest[start + 1..end];
+            let value = params.iter().find(|&&(key, _)| !wildcard.is_empty() && key == wildcard);
+            match value {
+                Some(&(key, value)) => {
+                    url.push_str(&rest[..start]);
+                    url.push_str(&http::url_encode(value).replace("%2F", "/"));
+                    used.push(key);
+                },
+                None => return Err(UrlError::MissingParameter(String::from(wildcard))),
+            }
+            rest = &rest[end + 1..];
+        }
+        url.push_str(rest);
+        let query = params.iter()
+            .filter(|&&(key, _)| !used.contains(&key))
+            .map(|&(key, value)| format!("{}={}", http::url_encode(key), http::url_encode(value)))
+            .collect::<Vec<String>>();
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+        Ok(url)
+    }
+
+    /// Same as `url_for` but returns the fully-qualified URL, using the
+    /// server's protocol, domain and port.
+    pub fn absolute_url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        self.url_for(name, params).map(|path| format!("{}{}", self.get_base_uri(), path))
+    }
+
     pub fn clone(&self) -> Configuration {
         Configuration {
             server: self.server.clone(),
-            routes: self.routes.clone()
+            routes: self.routes.clone(),
+            url_routes: self.url_routes.as_ref().map(|routes| routes.clone()),
+            hosts: self.hosts.clone(),
+            state: self.state.clone(),
+            middleware: self.middleware.clone(),
+            panic_handler: self.panic_handler.clone(),
+            metrics: self.metrics.clone(),
+            metrics_path: self.metrics_path.clone(),
+            health_checks: self.health_checks.clone(),
+            shutdown: self.shutdown.clone(),
+            error_handlers: self.error_handlers.clone(),
+            logger: self.logger.clone(),
         }
     }
+
+    /// Copies this configuration with a different route table.
+    fn with_routes_from(&self, routes: &Routes) -> Configuration {
+        let mut configs = self.clone();
+        configs.routes = routes.clone();
+        configs
+    }
 }
 
-/// This function serves static files based on the defined static directory.
-/// The default static files directory is `static/`. Static files are served at
-/// `/static/{file path under static directory}`
-fn static_route(request: Request, config: &Configuration) -> Response {
-    let file_to_get = request.get_url_args();
-    let static_dir = config.server.get_static_directory();
-    let filename = format!("{}{}", static_dir, file_to_get);
-    let file_to_serve = File::open(&filename);
-    match file_to_serve {
-        Ok(mut file) => {
-            let mut contents: Vec<u8> = Vec::new();
-            let result = file.read_to_end(&mut contents);
-            match result {
-                Ok(_) => http::ok_file(contents, get_content_type(&filename)),
-                Err(e) => {
-                    eprintln!("File read error: {}", e);
-                    http::not_found(String::from("Could not read file"), ContentType::TextHtml)
-                },
-            }
-        },
-        Err(e) => {
-            eprintln!("Could not find file to serve: {:?}",e);
-            http::not_found(String::from("Could not find resource"), ContentType::TextHtml)
-        },
+/// A route table answering requests for the hosts matching a pattern, added
+/// through `Configuration::with_host_routes`.
+struct VirtualHost {
+    pattern: String,
+    routes: Routes,
+}
+
+impl VirtualHost {
+    /// Returns true if the host is an exact match for the pattern or, for
+    /// patterns like `*.example.com`, is any subdomain of `example.com`.
+    fn matches(&self, host: &str) -> bool {
+        if self.pattern.starts_with("*.") {
+            let suffix = &self.pattern[1..];
+            host.len() > suffix.len() && host.ends_with(suffix)
+        } else {
+            self.pattern == host
+        }
     }
 }
 
+impl Clone for VirtualHost {
+    fn clone(&self) -> VirtualHost {
+        VirtualHost {
+            pattern: self.pattern.clone(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+/// Errors returned when building a URL from a named route.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UrlError {
+    /// No route was registered under the given name.
+    UnknownRoute(String),
+    /// The route has a wildcard that no parameter was given for. Unnamed
+    /// `{}` wildcards can't be filled in and are reported with an empty name.
+    MissingParameter(String),
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UrlError::UnknownRoute(ref name) => write!(f, "no route named '{}'", name),
+            UrlError::MissingParameter(ref name) => write!(f, "missing URL parameter '{}'", name),
+        }
+    }
+}
+
+impl Error for UrlError {}
+
 /// Retrieves a file as a String from the directory setup to contain
-/// HTML files. The default directory is `templates/`. This is meant to be
+/// HTML files. The default directory is `templates/`, and the files can be
+/// read from elsewhere with `Server::with_html_files`. Like static files,
+/// files reached through symlinks leading outside the directory are only
+/// read if the Server's `StaticOptions` allow it. This is meant to be
 /// used in conjunction with the functions that build a result from a body string
 /// and a content type.
 pub fn get_html(path: &str, config: &Configuration) -> String {
-    let html_dir = config.server.get_html_directory();
-    let filename = format!("{}{}", html_dir, path);
-    let file = File::open(filename);
+    let html_files = config.server.get_html_files();
+    let filename = html_files.describe(path);
+    let file = html_files.open(path, 0, &config.server.get_static_options());
     match file {
         Ok(mut f) => {
             let mut result_string: String = String::new();
@@ -378,22 +1407,53 @@ pub fn get_html(path: &str, config: &Configuration) -> String {
                     result_string
                 },
                 Err(e) => {
-                    eprintln!("File read error: {}", e);
+                    config.log(Level::Error, "File read error", &[("file", &filename), ("error", &e.to_string())]);
                     String::from("")
                 },
             }
         },
         Err(e) => {
-            eprintln!("{}", e);
+            config.log(Level::Warn, "Could not open HTML file", &[("file", &filename), ("error", &e.to_string())]);
             String::from("")
         },
     }
 }
 
-/// This only returns the not found route and is used when no
-/// route can be found for the given request.
-fn default_callback(_: Request, _: &Configuration) -> Response {
-    http::not_found(String::from("Route not found."), ContentType::TextHtml)
+/// This is used when no route can be found for the given request. Page
+/// requests are answered by the route table's SPA fallback if it has one.
+/// Otherwise, if the route exists for other methods a 405 listing them in
+/// an `Allow` header is returned, otherwise a 404. Requests under a route
+/// group with an error handler are passed on to that handler instead of
+/// the 404.
+fn default_callback(request: Request, configs: &Configuration) -> Response {
+    if let Some(response) = spa_fallback(&request, configs) {
+        return response;
+    }
+    let allowed = allowed_methods(&request, configs);
+    if !allowed.is_empty() {
+        return configs.error_response(405, "Method not allowed.", &request)
+            .with_header("Allow", &allowed.join(", "));
+    }
+    let handler = configs.routes.get_group(&request.get_path())
+        .and_then(|group| group.error_handler.clone());
+    match handler {
+        Some(handler) => handler.call(request, configs),
+        None => configs.error_response(404, "Route not found.", &request),
+    }
+}
+
+/// Returns the methods other than the request's own that the request's
+/// path would be routed for.
+fn allowed_methods(request: &Request, configs: &Configuration) -> Vec<String> {
+    let methods = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+    methods.iter()
+        .filter(|method| **method != request.get_method())
+        .filter(|method| {
+            let probe = request.clone().with_method(String::from(**method));
+            configs.server.route_request(&probe, &configs.routes).is_some()
+        })
+        .map(|method| String::from(*method))
+        .collect()
 }
 
 /// This returns the default homepage that only says: Good Job
@@ -411,8 +1471,8 @@ fn default_home(_: Request, _: &Configuration) -> Response {
 /// will parse `GET /home/113/static/115`
 /// as a Request to `my_other_func` with the args `["113", "stuff", "115"]`.
 /// Custom routers can be injected, however.
-fn default_router(request: &Request, routes: &Routes) -> (Vec<String>, CallBack) {
-    let mut func: Option<&CallBack> = None;
+fn default_router(request: &Request, routes: &Routes) -> Option<(Vec<String>, Arc<dyn Handler>)> {
+    let mut func: Option<&Arc<dyn Handler>> = None;
     let mut args: Vec<String> = Vec::new();
     let requested_route = request.get_route();
     let requested_method = request.get_method();
@@ -439,10 +1499,7 @@ fn default_router(request: &Request, routes: &Routes) -> (Vec<String>, CallBack)
     if args.len() > 1 {
         args.reverse();
     }
-    match func {
-        Some(f) => (args, *f),
-        None => (Vec::new(), default_callback),
-    }
+    func.map(|f| (args, f.clone()))
 }
 
 // Used for testing only
@@ -456,8 +1513,8 @@ fn get_request(method: &str, route: &str) -> Request {
 fn test_route_default() {
     let mut configs = Configuration::new();
     configs.routes.add_route("GET /", |_, _| http::ok(String::from("found"), ContentType::TextHtml));
-    let (_, callback) = default_router(&get_request("GET", "/"), &configs.routes);
-    let resp = callback(Request::new(), &configs);
+    let (_, callback) = default_router(&get_request("GET", "/"), &configs.routes).unwrap();
+    let resp = callback.call(Request::new(), &configs);
     assert_eq!(resp, http::ok(String::from("found"), ContentType::TextHtml));
 }
 
@@ -465,8 +1522,8 @@ fn test_route_default() {
 fn test_route_wildcard_simple() {
     let mut configs = Configuration::new();
     configs.routes.add_route("GET /{}", |_, _| http::ok(String::from("found"), ContentType::TextHtml));
-    let (args, callback) = default_router(&get_request("GET", "/home"), &configs.routes);
-    let resp = callback(Request::new(), &configs);
+    let (args, callback) = default_router(&get_request("GET", "/home"), &configs.routes).unwrap();
+    let resp = callback.call(Request::new(), &configs);
     assert_eq!(resp, http::ok(String::from("found"), ContentType::TextHtml));
     assert_eq!(args, vec![String::from("home")]);
 }
@@ -475,8 +1532,8 @@ fn test_route_wildcard_simple() {
 fn test_route_complex() {
     let mut configs = Configuration::new();
     configs.routes.add_route("GET /home/nope/ok", |_, _| http::ok(String::from("found"), ContentType::TextHtml));
-    let (_, callback) = default_router(&get_request("GET", "/home/nope/ok"), &configs.routes);
-    let resp = callback(Request::new(), &configs);
+    let (_, callback) = default_router(&get_request("GET", "/home/nope/ok"), &configs.routes).unwrap();
+    let resp = callback.call(Request::new(), &configs);
     assert_eq!(resp, http::ok(String::from("found"), ContentType::TextHtml));
 }
 
@@ -484,62 +1541,615 @@ fn test_route_complex() {
 fn test_route_wildcard_complex() {
     let mut configs = Configuration::new();
     configs.routes.add_route("GET /home/nope/{}", |_, _| http::ok(String::from("found"), ContentType::TextHtml));
-    let (args, callback) = default_router(&get_request("GET", "/home/nope/blah/whatever/113"), &configs.routes);
-    let resp = callback(Request::new(), &configs);
+    let (args, callback) = default_router(&get_request("GET", "/home/nope/blah/whatever/113"), &configs.routes).unwrap();
+    let resp = callback.call(Request::new(), &configs);
     assert_eq!(resp, http::ok(String::from("found"), ContentType::TextHtml));
     assert_eq!(args, vec![String::from("blah"), String::from("whatever"), String::from("113")]);
 }
 
+#[test]
+fn test_route_group_prefix() {
+    let mut configs = Configuration::new();
+    configs.routes.group("/api/v1/", |g| {
+        g.add_get("/users", |_, _| http::ok(String::from("users"), ContentType::TextHtml));
+        g.add_get("/", |_, _| http::ok(String::from("index"), ContentType::TextHtml));
+    });
+    assert!(configs.routes.contains_route("GET /api/v1/users"));
+    assert!(configs.routes.contains_route("GET /api/v1"));
+    let resp = route_request(get_request("GET", "/api/v1/users"), &configs);
+    assert_eq!(resp, http::ok(String::from("users"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_route_group_middleware_and_error_handler() {
+    struct Tag(&'static str);
+    impl Middleware for Tag {
+        fn after(&self, _: &Request, response: Response, _: &Configuration) -> Response {
+            response.with_header("X-Group", self.0)
+        }
+    }
+    let mut configs = Configuration::new();
+    configs.routes.group("/api", |g| {
+        g.add_get("/users", |_, _| http::ok(String::from("users"), ContentType::TextHtml));
+        g.add_middleware(Tag("api"));
+        g.set_error_handler(|_, _| http::not_found(String::from("no api"), ContentType::ApplicationJson));
+    });
+    let resp = route_request(get_request("GET", "/api/users"), &configs);
+    assert_eq!(resp, http::ok(String::from("users"), ContentType::TextHtml).with_header("X-Group", "api"));
+    let resp = route_request(get_request("GET", "/api/missing"), &configs);
+    assert_eq!(resp, http::not_found(String::from("no api"), ContentType::ApplicationJson).with_header("X-Group", "api"));
+    let resp = route_request(get_request("GET", "/apiary"), &configs);
+    assert_eq!(resp, http::not_found(String::from("Route not found."), ContentType::TextHtml));
+}
+
+#[test]
+fn test_middleware_short_circuit() {
+    struct Deny;
+    impl Middleware for Deny {
+        fn before(&self, _: &mut Request, _: &Configuration) -> Option<Response> {
+            Some(http::ok(String::from("denied"), ContentType::TextHtml).with_status(403))
+        }
+    }
+    let mut configs = Configuration::new();
+    configs.routes.group("/admin", |g| {
+        g.add_get("/", |_, _| http::ok(String::from("secret"), ContentType::TextHtml));
+        g.add_middleware(Deny);
+    });
+    let resp = route_request(get_request("GET", "/admin"), &configs);
+    assert_eq!(resp.get_status(), 403);
+    assert_eq!(resp.get_body(), b"denied".to_vec());
+}
+
+#[test]
+fn test_configuration_middleware_order() {
+    use std::sync::Mutex;
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+    impl Middleware for Trace {
+        fn before(&self, request: &mut Request, _: &Configuration) -> Option<Response> {
+            self.1.lock().unwrap().push(format!("before {}", self.0));
+            if request.get_path() == "/blocked" && self.0 == "auth" {
+                return Some(http::ok(String::from("blocked"), ContentType::TextHtml).with_status(401));
+            }
+            *request = request.clone().with_header((String::from("X-Seen"), String::from(self.0)));
+            None
+        }
+        fn after(&self, _: &Request, response: Response, _: &Configuration) -> Response {
+            self.1.lock().unwrap().push(format!("after {}", self.0));
+            response
+        }
+    }
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let configs = Configuration::new()
+        .with_middleware(Trace("log", trace.clone()))
+        .with_middleware(Trace("auth", trace.clone()))
+        .with_middleware(Trace("inner", trace.clone()))
+        .with_routes(Routes::new()
+            .with_route("GET /seen", |req: Request, _: &Configuration| req.get_header("X-Seen").unwrap_or_default()))
+        .with_host_routes("api.example.com", Routes::new());
+    let resp = route_request(get_request("GET", "/seen"), &configs);
+    assert_eq!(resp.get_body(), b"inner".to_vec());
+    assert_eq!(*trace.lock().unwrap(), vec!["before log", "before auth", "before inner",
+                                             "after inner", "after auth", "after log"]);
+    trace.lock().unwrap().clear();
+    let resp = route_request(get_request("GET", "/blocked"), &configs);
+    assert_eq!(resp.get_status(), 401);
+    assert_eq!(*trace.lock().unwrap(), vec!["before log", "before auth", "after auth", "after log"]);
+    trace.lock().unwrap().clear();
+    let request = get_request("GET", "/seen").with_header((String::from("Host"), String::from("api.example.com")));
+    assert_eq!(route_request(request, &configs).get_status(), 404);
+    assert_eq!(trace.lock().unwrap().len(), 6);
+}
+
+#[test]
+fn test_panics_become_server_errors() {
+    let mut configs = Configuration::new()
+        .with_routes(Routes::new()
+            .with_route("GET /boom", |_: Request, _: &Configuration| -> Response { panic!("boom") })
+            .with_route("GET /id/{}", |req: Request, _: &Configuration| -> Response {
+                let id: u32 = req.get_url_args().parse().unwrap();
+                http::ok(format!("{}", id), ContentType::TextHtml)
+            }));
+    let resp = route_request(get_request("GET", "/boom"), &configs);
+    assert_eq!(resp.get_status(), 500);
+    assert_eq!(resp.get_body(), b"Internal server error.".to_vec());
+    assert_eq!(configs.panic_count(), 1);
+
+    configs.set_panic_handler(|req: Request, _: &Configuration| (500, format!("failed on {}", req.get_route())));
+    let copy = configs.clone();
+    let resp = route_request(get_request("GET", "/id/abc"), &copy);
+    assert_eq!(resp, http::ok(String::from("failed on GET /id/abc"), ContentType::TextHtml).with_status(500));
+    assert_eq!(configs.panic_count(), 2);
+    assert_eq!(route_request(get_request("GET", "/id/7"), &configs).get_body(), b"7".to_vec());
+    assert_eq!(panic_message(&String::from("owned")), "owned");
+}
+
+#[test]
+fn test_handlers_see_request_id() {
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_route("GET /id", |req: Request, _: &Configuration| req.get_request_id()));
+    let request = get_request("GET", "/id").with_header((String::from("X-Request-Id"), String::from("trace-7")));
+    assert_eq!(route_request(request, &configs).get_body(), b"trace-7".to_vec());
+    assert_eq!(route_request(get_request("GET", "/id"), &configs).get_body().len(), 36);
+}
+
+#[test]
+fn test_mount_strips_prefix() {
+    let billing = Routes::new()
+        .with_route("GET /invoices/{}", |req, _| {
+            http::ok(format!("{} {} {}", req.get_mount_point(), req.get_path(), req.get_url_args()), ContentType::TextHtml)
+        });
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_mount("/billing/", billing));
+    let resp = route_request(get_request("GET", "/billing/invoices/42"), &configs);
+    assert_eq!(resp, http::ok(String::from("/billing /invoices/42 42"), ContentType::TextHtml));
+    let resp = route_request(get_request("GET", "/billing"), &configs);
+    assert_eq!(resp, http::ok(String::from("Good Job."), ContentType::TextHtml));
+}
+
+#[test]
+fn test_mount_own_router_and_static_dir() {
+    fn everything(_: &Request, _: &Routes) -> Option<(Vec<String>, Arc<dyn Handler>)> {
+        Some((Vec::new(), Arc::new(|_: Request, _: &Configuration| http::ok(String::from("custom"), ContentType::TextHtml))))
+    }
+    let dir = std::env::temp_dir().join("servo_test_mount_static");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("app.css"), "body {}").unwrap();
+    let assets = Routes::new().with_static_dir(&format!("{}/", dir.display()));
+    let custom = Routes::new().with_handler_router(everything);
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_mount("/assets", assets).with_mount("/custom", custom));
+    let resp = route_request(get_request("GET", "/assets/static/app.css"), &configs);
+    assert_eq!(resp.buffered().unwrap().get_body(), b"body {}".to_vec());
+    let resp = route_request(get_request("GET", "/custom/anything/at/all"), &configs);
+    assert_eq!(resp, http::ok(String::from("custom"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_callback_router() {
+    fn hello(request: Request, _: &Configuration) -> Response {
+        http::ok(request.get_url_args(), ContentType::TextHtml)
+    }
+    fn everything(request: &Request, _: &Routes) -> (Vec<String>, CallBack) {
+        (vec![request.get_path()], hello)
+    }
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_router(everything));
+    let resp = route_request(get_request("GET", "/any/path"), &configs.clone());
+    assert_eq!(resp, http::ok(String::from("/any/path"), ContentType::TextHtml));
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_mount("/old", Routes::new().with_router(everything)));
+    let resp = route_request(get_request("GET", "/old/thing"), &configs);
+    assert_eq!(resp, http::ok(String::from("/thing"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_url_for_named_routes() {
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_domain("example.com").with_port("8080"))
+        .with_routes(Routes::new()
+            .with_named_route("user_profile", "GET /users/{id}", |req, _| http::ok(req.get_url_args(), ContentType::TextHtml))
+            .with_mount("/billing", Routes::new().with_named_route("invoice", "GET /invoices/{number}", default_home)));
+    assert_eq!(configs.url_for("user_profile", &[("id", "42")]), Ok(String::from("/users/42")));
+    assert_eq!(configs.url_for("user_profile", &[("id", "a b"), ("tab", "posts&more")]),
+               Ok(String::from("/users/a%20b?tab=posts%26more")));
+    assert_eq!(configs.absolute_url_for("invoice", &[("number", "7")]),
+               Ok(String::from("http://example.com:8080/billing/invoices/7")));
+    assert_eq!(configs.url_for("user_profile", &[]), Err(UrlError::MissingParameter(String::from("id"))));
+    assert_eq!(configs.url_for("nope", &[]), Err(UrlError::UnknownRoute(String::from("nope"))));
+    let resp = route_request(get_request("GET", "/users/42"), &configs);
+    assert_eq!(resp, http::ok(String::from("42"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_url_for_inside_mount() {
+    let billing = Routes::new()
+        .with_named_route("invoice", "GET /invoices/{id}", |_, configs: &Configuration| {
+            let invoice = configs.url_for("invoice", &[("id", "7")]).unwrap();
+            let profile = configs.url_for("user_profile", &[("id", "42")]).unwrap();
+            http::ok(format!("{} {}", invoice, profile), ContentType::TextHtml)
+        });
+    let configs = Configuration::new()
+        .with_routes(Routes::new()
+            .with_named_route("user_profile", "GET /users/{id}", default_home)
+            .with_mount("/billing", billing));
+    let resp = route_request(get_request("GET", "/billing/invoices/3"), &configs);
+    assert_eq!(resp, http::ok(String::from("/billing/invoices/7 /users/42"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_url_for_group_routes() {
+    let mut configs = Configuration::new();
+    configs.routes.group("/api/v1", |g| {
+        g.add_named_route("api_user", "GET /users/{id}", default_home);
+    });
+    assert!(configs.routes.contains_route("GET /api/v1/users/{}"));
+    assert_eq!(configs.url_for("api_user", &[("id", "5")]), Ok(String::from("/api/v1/users/5")));
+}
+
+#[test]
+fn test_virtual_hosts() {
+    let configs = Configuration::new()
+        .with_host_routes("api.example.com", Routes::new()
+            .with_route("GET /", |_, configs| http::ok(configs.get_base_uri(), ContentType::TextHtml)))
+        .with_host_routes("*.example.com", Routes::new()
+            .with_route("GET /", |_, _| http::ok(String::from("tenant"), ContentType::TextHtml)));
+    let request = |host: &str| get_request("GET", "/").with_header((String::from("Host"), String::from(host)));
+    let resp = route_request(request("API.example.com:8000"), &configs);
+    assert_eq!(resp, http::ok(String::from("http://api.example.com:8000"), ContentType::TextHtml));
+    let resp = route_request(request("acme.example.com"), &configs);
+    assert_eq!(resp, http::ok(String::from("tenant"), ContentType::TextHtml));
+    let resp = route_request(request("example.com"), &configs);
+    assert_eq!(resp, http::ok(String::from("Good Job."), ContentType::TextHtml));
+    let resp = route_request(get_request("GET", "/"), &configs);
+    assert_eq!(resp, http::ok(String::from("Good Job."), ContentType::TextHtml));
+}
+
+#[test]
+fn test_closure_handlers_and_state() {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Greeting(String);
+    struct Visits(Mutex<Vec<String>>);
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let mut configs = Configuration::new()
+        .with_state(Greeting(String::from("hello")))
+        .with_state(Visits(Mutex::new(Vec::new())));
+    configs.routes.add_get("/hello/{}", move |req, configs: &Configuration| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        let greeting = &configs.state::<Greeting>().unwrap().0;
+        configs.state::<Visits>().unwrap().0.lock().unwrap().push(req.get_url_args());
+        http::ok(format!("{} {}", greeting, req.get_url_args()), ContentType::TextHtml)
+    });
+    let resp = route_request(get_request("GET", "/hello/bob"), &configs.clone());
+    assert_eq!(resp, http::ok(String::from("hello bob"), ContentType::TextHtml));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+    assert_eq!(*configs.state::<Visits>().unwrap().0.lock().unwrap(), vec![String::from("bob")]);
+    assert!(configs.state::<String>().is_none());
+}
+
+#[test]
+fn test_custom_handler_type() {
+    struct Echo;
+    impl Handler for Echo {
+        fn call(&self, request: Request, _: &Configuration) -> Response {
+            http::ok(request.get_path(), ContentType::TextHtml)
+        }
+    }
+    let configs = Configuration::new()
+        .with_routes(Routes::new().with_handler("GET /echo", Echo));
+    let resp = route_request(get_request("GET", "/echo"), &configs);
+    assert_eq!(resp, http::ok(String::from("/echo"), ContentType::TextHtml));
+}
+
+#[test]
+fn test_handlers_returning_into_response() {
+    use http::HttpError;
+    use http::json::JsonValue;
+    fn double(request: Request, _: &Configuration) -> Result<String, HttpError> {
+        let number: i32 = request.get_url_args().parse()?;
+        Ok(format!("{}", number * 2))
+    }
+    let mut configs = Configuration::new();
+    configs.routes.add_get("/double/{}", double);
+    configs.routes.add_get("/text", |_, _| "plain");
+    configs.routes.add_post("/created", |_, _| (201, "made it"));
+    configs.routes.add_get("/json", |_, _| JsonValue::object(vec![("ok", JsonValue::from(true))]));
+    let resp = route_request(get_request("GET", "/double/21"), &configs);
+    assert_eq!(resp, http::ok(String::from("42"), ContentType::TextHtml));
+    let resp = route_request(get_request("GET", "/double/abc"), &configs);
+    assert_eq!(resp.get_status(), 400);
+    let resp = route_request(get_request("GET", "/text"), &configs);
+    assert_eq!(resp, http::ok(String::from("plain"), ContentType::TextHtml));
+    let resp = route_request(get_request("POST", "/created"), &configs);
+    assert_eq!(resp, http::ok(String::from("made it"), ContentType::TextHtml).with_status(201));
+    let resp = route_request(get_request("GET", "/json"), &configs);
+    assert_eq!(resp.get_body(), b"{\"ok\":true}".to_vec());
+    assert_eq!(resp.get_content_type().stringify(), "application/json");
+}
+
+#[test]
+fn test_max_request_size() {
+    use std::io::Write;
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_max_request_size(256))
+        .with_routes(Routes::new().with_route("POST /echo", |request: Request, _: &Configuration| request.get_body_string()));
+    let send = |parts: &[&[u8]]| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        for part in parts {
+            client.write_all(part).unwrap();
+        }
+        handle_request(stream, &configs, configs.metrics.track_connection());
+        let mut reply = Vec::new();
+        let _ = client.read_to_end(&mut reply);
+        String::from_utf8_lossy(&reply).into_owned()
+    };
+    let reply = send(&[b"POST /echo HTTP/1.1\r\nContent-Len", b"gth: 5\r", b"\n\r", b"\nhe", b"llo"]);
+    assert!(reply.starts_with("HTTP/1.1 200"));
+    assert!(reply.ends_with("hello"));
+    let reply = send(&[b"POST /echo HTTP/1.1\r\nContent-Length: 10000000\r\n\r\nstart"]);
+    assert!(reply.starts_with("HTTP/1.1 413"));
+    let long_header = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n", "a".repeat(512));
+    assert!(send(&[long_header.as_bytes()]).starts_with("HTTP/1.1 413"));
+}
+
 /// Takes a Request along with the current Servo instances' configuration and 
-/// returns a Response based on how the route map is currently setup.
+/// returns a Response based on how the route map is currently setup. If any
+/// virtual hosts are configured, the request's `Host` header picks the route
+/// table, and CallBacks for a matched host see it as the server's domain.
+/// Middleware added to the Configuration runs around all of this. A panic
+/// anywhere in the middleware or CallBack is caught and answered with a 500.
+/// Requests without an ID are given one first, and every request is
+/// counted in the Configuration's Metrics.
 pub fn route_request(request: Request, configs: &Configuration) -> Response {
-    let (args, callback) = configs.server.route_request(&request, &configs.routes);
-    callback(request.with_url_args(args).query_params_from_route(), configs)
+    let started = Instant::now();
+    let request = request.ensure_request_id();
+    let method = request.get_method();
+    let received = request.clone();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_middleware(&configs.middleware, request, configs, &route_to_host)
+    }));
+    let response = match result {
+        Ok(response) => response,
+        Err(payload) => handle_panic(received, &*payload, configs),
+    };
+    let pattern = response.get_route_pattern().unwrap_or_else(|| String::from("unmatched"));
+    configs.metrics.observe(&pattern, &method, response.get_status(), started.elapsed());
+    response
+}
+
+/// Records a panic caught while answering a request and builds the 500
+/// Response for it, using the Configuration's panic handler if it has one.
+fn handle_panic(request: Request, payload: &(dyn Any + Send), configs: &Configuration) -> Response {
+    configs.metrics.record_panic();
+    configs.log(Level::Error, "Panic while handling request",
+                &[("route", &request.get_route()),
+                  ("request_id", &request.get_request_id()),
+                  ("panic", &panic_message(payload))]);
+    let response = panic::catch_unwind(AssertUnwindSafe(|| match configs.panic_handler {
+        Some(ref handler) => handler.call(request, configs),
+        None => configs.error_response(500, "Internal server error.", &request),
+    }));
+    response.unwrap_or_else(|_| internal_server_error())
+}
+
+/// Pulls the message out of a panic payload, which is a `&str` or a String
+/// for anything raised through `panic!`.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+/// The page sent when a request panics and its error handler panics too.
+fn internal_server_error() -> Response {
+    Response::new()
+        .with_status(500)
+        .with_content_type(ContentType::TextHtml)
+        .with_body(Vec::from("Internal server error.".as_bytes()))
 }
 
+/// Picks the route table for the request's host and dispatches to it.
+fn route_to_host(request: Request, configs: &Configuration) -> Response {
+    if let Some(response) = builtin_route(&request, configs) {
+        return response;
+    }
+    let host = request.get_host();
+    match configs.find_host(&host) {
+        Some(vhost) => {
+            let mut host_configs = configs.with_routes_from(&vhost.routes);
+            host_configs.server = host_configs.server.with_domain(&host);
+            dispatch(request, &host_configs)
+        },
+        None => dispatch(request, configs),
+    }
+}
+
+/// Answers the routes Servo serves itself on every host when they are
+/// configured: the metrics and the health probes.
+fn builtin_route(request: &Request, configs: &Configuration) -> Option<Response> {
+    if request.get_method() != "GET" {
+        return None;
+    }
+    let path = request.get_path();
+    if configs.metrics_path.as_ref() == Some(&path) {
+        return Some(http::ok(configs.metrics.render(), ContentType::TextPlain).with_route_pattern(&path));
+    }
+    let checks = configs.health_checks.as_ref()?;
+    let response = match path.as_str() {
+        "/healthz" => checks.liveness(),
+        "/readyz" => checks.readiness(configs.shutdown.is_shutting_down()),
+        _ => return None,
+    };
+    Some(response.with_route_pattern(&path))
+}
+
+/// Finds and calls the handler for a request within a single route table.
+/// Requests under a mount are passed on to the mounted routes and requests
+/// under a route group are run through that group's middleware. Requests
+/// that match no route are answered by `default_callback`. Responses from
+/// a matched route carry its pattern for the metrics.
+fn dispatch(request: Request, configs: &Configuration) -> Response {
+    if let Some(mount) = configs.routes.get_mount(&request.get_path()) {
+        let mut mounted = configs.with_routes_from(&mount.routes);
+        if mounted.url_routes.is_none() {
+            mounted.url_routes = Some(configs.routes.clone());
+        }
+        return dispatch(request.mounted_at(&mount.prefix), &mounted);
+    }
+    let (args, handler, matched): (Vec<String>, Arc<dyn Handler>, bool) =
+        match configs.server.route_request(&request, &configs.routes) {
+            Some((args, handler)) => (args, handler, true),
+            None => (Vec::new(), Arc::new(default_callback), false),
+        };
+    let request = request.with_url_args(args).query_params_from_route();
+    let pattern = if matched { Some(route_pattern(&request, &configs.routes)) } else { None };
+    let response = match configs.routes.get_group(&request.get_path()) {
+        Some(group) => run_middleware(&group.middleware, request, configs, &*handler),
+        None => handler.call(request, configs),
+    };
+    match pattern {
+        Some(ref pattern) if response.get_route_pattern().is_none() => response.with_route_pattern(pattern),
+        _ => response,
+    }
+}
+
+/// Works out which route in the table a routed request matched, such as
+/// `/users/{}` for `/users/42`, including the prefix of any mount it is
+/// under. Routes picked by a custom Router that don't follow the default
+/// Router's rules are reported as `other`.
+fn route_pattern(request: &Request, routes: &Routes) -> String {
+    let method = request.get_method();
+    let path = request.get_path();
+    let args = request.get_url_arg_list();
+    let pattern = if args.is_empty() && routes.contains_route(&format!("{} {}", method, path)) {
+        path
+    } else {
+        let segments: Vec<&str> = path.split('/').skip(1).collect();
+        let kept = segments.len().saturating_sub(args.len());
+        let wildcard = segments[..kept].iter()
+            .map(|segment| format!("{}/", segment))
+            .collect::<String>();
+        let candidate = format!("/{}{{}}", wildcard);
+        if routes.contains_route(&format!("{} {}", method, candidate)) {
+            candidate
+        } else {
+            return String::from("other");
+        }
+    };
+    format!("{}{}", request.get_mount_point(), pattern)
+}
+
+/// The largest request the server reads by default, headers and body
+/// together: 1 MiB.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
 /// Takes a TCP buffer, reads whatever is in it and outputs
-/// the contents to a u8 Vector. If there is an error reading
-/// the buffer, an error is printed to console and an empty
-/// vector is returned.
-fn read_input_buffer(mut stream : &TcpStream) -> Vec<u8> {
+/// the contents to a u8 Vector. Keeps reading until the headers have
+/// ended and as much body as the `Content-Length` header promises has
+/// arrived. If there is an error reading the buffer, an error is printed
+/// to console and whatever was read so far is returned. Reading stops with
+/// a 413 error as soon as the request is known to be larger than the
+/// Server's maximum request size.
+fn read_input_buffer(mut stream : &TcpStream, configs: &Configuration) -> (Vec<u8>, Option<HttpError>) {
+    let max_size = configs.server.get_max_request_size();
+    let too_large = || HttpError::new(413, "Request is too large.");
     let mut buffer = [0u8 ; 4096];
-    match stream.read(&mut buffer) {
-        Ok(_) => {
-            let request = String::from_utf8_lossy(&buffer);
-            Vec::from(request.as_bytes())
-        },
-        Err(e) => {
-            eprintln!("Input Stream Error: {}", e);
-            vec![]
-        },
+    let mut request: Vec<u8> = Vec::new();
+    // How far the end of the headers has been looked for, and how long the
+    // whole request is once they have been found.
+    let mut scanned = 0;
+    let mut total_length: Option<usize> = None;
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return (request, None),
+            Ok(read) => {
+                request.extend_from_slice(&buffer[..read]);
+                if total_length.is_none() {
+                    match http::find_body_start(&request[scanned..]) {
+                        Some(body_start) => {
+                            let body_start = scanned + body_start;
+                            total_length = Some(body_start.saturating_add(content_length(&request[..body_start])));
+                        },
+                        // The blank line ending the headers may be split
+                        // across reads, so look again at its first bytes.
+                        None => scanned = request.len().saturating_sub(3),
+                    }
+                }
+                match total_length {
+                    Some(total) if total > max_size => return (request, Some(too_large())),
+                    Some(total) if request.len() >= total => return (request, None),
+                    None if request.len() > max_size => return (request, Some(too_large())),
+                    _ => {},
+                }
+            },
+            Err(e) => {
+                configs.log(Level::Error, "Input stream error", &[("error", &e.to_string())]);
+                return (request, None);
+            },
+        }
     }
 }
 
-/// Takes a u8 array and the TCP stream and writes those bytes to the stream.
-/// Prints 'replied' on successful write and an error message on failure.
-fn write_output_buffer(mut stream : &TcpStream, to_write : &[u8]) {
-    match stream.write(to_write) {
-        Ok(_) => (),
-        Err(e) => eprintln!("Failed to reply to request: {}", e),
+/// Returns the `Content-Length` a raw request head declares, or 0.
+fn content_length(head: &[u8]) -> usize {
+    Request::from(&String::from_utf8_lossy(head))
+        .get_header("Content-Length")
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// Writes the Response to the TCP stream, reading a streaming body as it
+/// goes, and logs an error on failure. Returns how many body bytes were sent.
+fn write_output_buffer(stream : &TcpStream, response : Response, configs: &Configuration) -> u64 {
+    let mut writer = BufWriter::new(stream);
+    match response.write_to(&mut writer) {
+        Ok(written) => written,
+        Err(e) => {
+            configs.log(Level::Error, "Failed to reply to request", &[("error", &e.to_string())]);
+            0
+        },
     }
 }
 
 /// Reads from the input buffer and transforms that Vec<u8> into a String.
 /// Then it transforms that String into a Request object and asks for the
 /// Response String from the http_requests file functions and writes that output
-/// to the stream.
-fn handle_request(stream : TcpStream, configs: &Configuration) {
-    let vector_buffer = read_input_buffer(&stream);
-    let request_str =
-        String::from(match str::from_utf8(&vector_buffer) {
-            Ok(x) => x,
-            Err(err) => {
-                eprintln!("Error: {}", err);
-                ""
-            },
-        });
-    let request_obj = http::Request::from(&request_str);
-    let response = route_request(request_obj, configs);
-    let response_bytes = response.byteify();
-    write_output_buffer(&stream, &response_bytes);
+/// to the stream. The request's ID is sent back in the `X-Request-Id` header
+/// and the request is then recorded in the access log, if the Server has one.
+/// The connection stays counted as open until the guard is dropped here.
+fn handle_request(stream : TcpStream, configs: &Configuration, _connection: GaugeGuard) {
+    let (vector_buffer, read_error) = read_input_buffer(&stream, configs);
+    if vector_buffer.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    let access_log = configs.server.get_access_log();
+    let mut entry = access_log.as_ref().map(|_| {
+        let client_ip = stream.peer_addr()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_else(|_| String::from("-"));
+        AccessLogEntry::new(&client_ip, &vector_buffer)
+    });
+    let parsed = match read_error {
+        Some(e) => Err(e),
+        None => http::Request::parse(&vector_buffer),
+    };
+    let (request_id, mut response) = match parsed {
+        Ok(request_obj) => {
+            let request_obj = request_obj.ensure_request_id();
+            entry = entry.map(|entry| entry.with_request(&request_obj));
+            (request_obj.get_request_id(), route_request(request_obj, configs))
+        },
+        Err(e) => {
+            let request_obj = Request::new().ensure_request_id();
+            entry = entry.map(|entry| entry.with_request(&request_obj));
+            let response = configs.error_response(e.get_status(), &e.get_message(), &request_obj);
+            configs.metrics.observe("unparsed", "-", response.get_status(), started.elapsed());
+            (request_obj.get_request_id(), response)
+        },
+    };
+    let has_id = response.get_headers()
+        .map(|headers| headers.keys().any(|key| key.eq_ignore_ascii_case("X-Request-Id")))
+        .unwrap_or(false);
+    if !has_id {
+        response.add_header("X-Request-Id", &request_id);
+    }
+    let status = response.get_status();
+    let body_length = write_output_buffer(&stream, response, configs);
+    if let (Some(access_log), Some(entry)) = (access_log, entry) {
+        let entry = entry
+            .with_status(status)
+            .with_bytes(body_length as usize)
+            .with_latency(started.elapsed());
+        if let Err(e) = access_log.record(&entry) {
+            configs.log(Level::Error, "Could not write to access log", &[("error", &e.to_string())]);
+        }
+    }
 }
@@ -0,0 +1,1024 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{Configuration, is_under_prefix, normalize_prefix};
+use http::{self, HttpError, Request, Response};
+use http::content_type::{ContentType, get_content_type};
+use http::date::DateTime;
+use http::json::JsonValue;
+use logging::Level;
+use vfs::{DirectoryFiles, FileInfo, FileSystem};
+
+/// This function serves static files based on the defined static directory.
+/// The default static files directory is `static/`. Static files are served at
+/// `/static/{file path under static directory}` by the default `static` mount,
+/// see `StaticMount`. Paths that would leave the static directory are
+/// refused, see `resolve_static_path`. Files are sent
+/// with `ETag` and `Last-Modified` headers, and a `304 Not Modified` with no
+/// body is sent instead if the client's copy is still current. A `Range`
+/// header is answered with just the requested bytes, see `requested_ranges`.
+/// A directory is answered with its `index.html`, or a listing of its
+/// contents if the Server allows that, after redirecting to the URL ending
+/// with a `/` so relative links in the page work. Files are read through
+/// the Server's `FileSystem`, see `Server::with_static_files`. Page requests
+/// for missing files get the route table's `SpaFallback`, if it has one.
+pub fn static_route(request: Request, config: &Configuration) -> Response {
+    serve_static(request, config, None, CachePolicy::Unspecified)
+}
+
+/// Serves a file from `files`, or from the route table's static directory
+/// or Server's static files if there are none, adding the cache policy's
+/// `Cache-Control` header to anything served from it.
+fn serve_static(request: Request, config: &Configuration, files: Option<Arc<dyn FileSystem>>,
+                cache_policy: CachePolicy) -> Response {
+    with_cache_policy(find_static(request, config, files), cache_policy)
+}
+
+/// Adds the cache policy's `Cache-Control` header to a Response serving a
+/// file, but not to errors.
+fn with_cache_policy(mut response: Response, cache_policy: CachePolicy) -> Response {
+    if let (Some(cache_control), 200 | 206 | 304) = (cache_policy.header_value(), response.get_status()) {
+        response.add_header("Cache-Control", &cache_control);
+    }
+    response
+}
+
+/// Returns where a route table serves static files from when a mount
+/// doesn't say: its own static directory or the Server's static files.
+fn default_static_files(config: &Configuration) -> Arc<dyn FileSystem> {
+    match config.routes.get_static_directory() {
+        Some(dir) => Arc::new(DirectoryFiles::new(&dir)),
+        None => config.server.get_static_files(),
+    }
+}
+
+fn find_static(request: Request, config: &Configuration, files: Option<Arc<dyn FileSystem>>) -> Response {
+    let file_to_get = request.get_url_arg_list().join("/");
+    let files = files.unwrap_or_else(|| default_static_files(config));
+    let options = config.server.get_static_options();
+    let found = clean_static_path(&file_to_get, &options)
+        .and_then(|path| files.metadata(&path, &options).map(|info| (path, info)));
+    let (file_path, info) = match found {
+        Ok(found) => found,
+        Err(e) => {
+            if e.get_status() == 404 {
+                if let Some(response) = spa_fallback(&request, config) {
+                    return response;
+                }
+            }
+            let (log_message, message) = if e.get_status() == 404 {
+                ("Could not find file to serve", "Could not find resource")
+            } else {
+                ("Refused static file request", "Invalid path")
+            };
+            config.log(Level::Warn, log_message,
+                       &[("file", &files.describe(&file_to_get)), ("error", &e.get_message())]);
+            return config.error_response(e.get_status(), message, &request);
+        },
+    };
+    if info.is_dir() {
+        let path = format!("{}{}", request.get_mount_point(), request.get_path());
+        if !path.ends_with('/') {
+            let query = request.get_query_string();
+            let location = if query.is_empty() { format!("{}/", path) } else { format!("{}/?{}", path, query) };
+            return Response::new().with_status(301).with_header("Location", &location);
+        }
+        let index_path = join_path(&file_path, "index.html");
+        if let Ok(index) = files.metadata(&index_path, &options) {
+            if !index.is_dir() {
+                return file_response(request, config, &*files, index_path, index);
+            }
+        }
+        if options.directory_listing {
+            return directory_listing(&request, &*files, &file_path, &path, &options);
+        }
+        if let Some(response) = spa_fallback(&request, config) {
+            return response;
+        }
+        config.log(Level::Warn, "Could not find file to serve",
+                   &[("file", &files.describe(&file_to_get)), ("error", "directory has no index.html")]);
+        return config.error_response(404, "Could not find resource", &request);
+    }
+    file_response(request, config, &*files, file_path, info)
+}
+
+/// Serves a single file that has already been found by `static_route` at
+/// `path` in `files`. If there is a precompressed sibling of the file the
+/// client accepts, that is sent instead.
+fn file_response(request: Request, config: &Configuration, files: &dyn FileSystem, path: String, info: FileInfo) -> Response {
+    let content_type = get_content_type(&path);
+    let options = config.server.get_static_options();
+    let variants = precompressed_variants(files, &path, &options);
+    let chosen = variants.iter()
+        .map(|&(coding, ref path, ref info)| (request.encoding_quality(coding), coding, path, info))
+        .filter(|&(quality, _, _, _)| quality > 0.0)
+        .fold(None, |best: Option<(f32, &str, &String, &FileInfo)>, variant| match best {
+            Some(best) if best.0 >= variant.0 => Some(best),
+            _ => Some(variant),
+        });
+    let (path, info, encoding) = match chosen {
+        Some((_, coding, path, info)) => (path.clone(), info.clone(), Some(coding)),
+        None => (path, info, None),
+    };
+    let etag = info.get_etag().unwrap_or_default();
+    let etag = match encoding {
+        // Each encoding is its own representation and needs its own ETag.
+        Some(coding) => format!("{}-{}\"", etag.trim_end_matches('"'), coding),
+        None => etag,
+    };
+    let last_modified = info.get_modified();
+    let mut response = Response::new()
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("ETag", &etag);
+    if let Some(last_modified) = last_modified {
+        response.add_header("Last-Modified", &last_modified.to_http_date());
+    }
+    if let Some(coding) = encoding {
+        response.add_header("Content-Encoding", coding);
+    }
+    if !variants.is_empty() {
+        response.add_header("Vary", "Accept-Encoding");
+    }
+    if is_not_modified(&request, Some(&etag), last_modified) {
+        return response.with_status(304);
+    }
+    let length = info.get_len();
+    let response = response.with_status(200).with_content_type(content_type);
+    let ranges = match requested_ranges(&request, length, Some(&etag), last_modified) {
+        None => match files.open(&path, 0, &options) {
+            Ok(file) => return response.with_stream(file, Some(length)),
+            Err(e) => {
+                config.log(Level::Warn, "Could not find file to serve",
+                           &[("file", &files.describe(&path)), ("error", &e.to_string())]);
+                return config.error_response(404, "Could not find resource", &request);
+            },
+        },
+        Some(ranges) => ranges,
+    };
+    if ranges.is_empty() {
+        return config.error_response(416, "Requested range not satisfiable", &request)
+            .with_header("Content-Range", &format!("bytes */{}", length));
+    }
+    let open_range = |first: u64, last: u64| -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(files.open(&path, first, &options)?.take(last - first + 1)))
+    };
+    match ranged_response(response, length, &ranges, open_range) {
+        Ok(response) => response,
+        Err(e) => {
+            config.log(Level::Error, "File read error", &[("file", &files.describe(&path)), ("error", &e.to_string())]);
+            config.error_response(404, "Could not read file", &request)
+        },
+    }
+}
+
+/// Content codings static files may have precompressed siblings for, in the
+/// order they are preferred, along with the extension of the sibling.
+pub const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// Returns the precompressed siblings of the file at `path` in `files`,
+/// such as `app.js.gz` for `app.js`, with their content coding.
+fn precompressed_variants(files: &dyn FileSystem, path: &str, options: &StaticOptions)
+                          -> Vec<(&'static str, String, FileInfo)> {
+    PRECOMPRESSED.iter()
+        .filter_map(|&(coding, extension)| {
+            let path = format!("{}{}", path, extension);
+            files.metadata(&path, options).ok()
+                .filter(|info| !info.is_dir())
+                .map(|info| (coding, path, info))
+        })
+        .collect()
+}
+
+/// Joins a file name onto a relative path, which is empty for the top
+/// directory.
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() { String::from(name) } else { format!("{}/{}", path, name) }
+}
+
+/// A URL prefix serving files from a directory, such as `/assets` serving
+/// `public/`. Every route table starts with a mount named `static` serving
+/// the static directory at `/static`, and more can be added with
+/// `Routes::with_static_mount`. Adding a mount with the name of an existing
+/// one replaces it, so the default can be moved to another prefix. A mount
+/// without a directory of its own serves the route table's static directory
+/// or the Server's static files.
+#[derive(Clone)]
+pub struct StaticMount {
+    name: String,
+    prefix: String,
+    dir: Option<String>,
+    files: Option<Arc<dyn FileSystem>>,
+    cache_policy: CachePolicy,
+}
+
+impl fmt::Debug for StaticMount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StaticMount")
+            .field("name", &self.name)
+            .field("prefix", &self.prefix)
+            .field("dir", &self.dir)
+            .field("files", &self.files.as_ref().map(|_| "FileSystem"))
+            .field("cache_policy", &self.cache_policy)
+            .finish()
+    }
+}
+
+impl StaticMount {
+    /// Creates a mount serving the static directory at the given prefix,
+    /// such as `/assets`.
+    pub fn new(name: &str, prefix: &str) -> StaticMount {
+        StaticMount {
+            name: String::from(name),
+            prefix: normalize_prefix(prefix),
+            dir: None,
+            files: None,
+            cache_policy: CachePolicy::Unspecified,
+        }
+    }
+
+    /// Serves files from the given directory instead of the static directory.
+    pub fn with_dir(mut self, dir: &str) -> StaticMount {
+        self.dir = Some(String::from(dir));
+        self.files = Some(Arc::new(DirectoryFiles::new(dir)));
+        self
+    }
+
+    /// Serves files from the given `FileSystem`, such as `EmbeddedFiles`,
+    /// instead of the static directory.
+    pub fn with_files<F: FileSystem + 'static>(mut self, files: F) -> StaticMount {
+        self.dir = None;
+        self.files = Some(Arc::new(files));
+        self
+    }
+
+    pub fn with_cache_policy(mut self, cache_policy: CachePolicy) -> StaticMount {
+        self.cache_policy = cache_policy;
+        self
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Returns the prefix in the format `/assets` (no trailing slash)
+    pub fn get_prefix(&self) -> String {
+        self.prefix.clone()
+    }
+
+    pub fn get_directory(&self) -> Option<String> {
+        self.dir.clone()
+    }
+
+    pub fn get_files(&self) -> Option<Arc<dyn FileSystem>> {
+        self.files.clone()
+    }
+
+    pub fn get_cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Returns the route key this mount is served under, such as
+    /// `GET /assets/{}`.
+    pub fn route_key(&self) -> String {
+        format!("GET {}/{{}}", self.prefix)
+    }
+
+    /// Serves a request for a file under this mount.
+    pub fn serve(&self, request: Request, config: &Configuration) -> Response {
+        serve_static(request, config, self.files.clone(), self.cache_policy)
+    }
+}
+
+/// Single page app mode, for frontends that route on the client such as
+/// React apps. Page requests that match no route or static file are
+/// answered with a fallback file, usually `index.html`, instead of a 404,
+/// so the app can show the page for the URL itself. Only `GET` and `HEAD`
+/// requests whose `Accept` header asks for HTML get the fallback, and
+/// requests under an excluded prefix such as `/api` never do, so API
+/// clients still see their 404s. Set it on a route table with
+/// `Routes::with_spa_fallback`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaFallback {
+    file: String,
+    dir: Option<String>,
+    excluded_prefixes: Vec<String>,
+    cache_policy: CachePolicy,
+}
+
+impl SpaFallback {
+    /// Creates a fallback serving the given file, such as `index.html`,
+    /// from the static files.
+    pub fn new(file: &str) -> SpaFallback {
+        SpaFallback {
+            file: String::from(file),
+            dir: None,
+            excluded_prefixes: Vec::new(),
+            cache_policy: CachePolicy::Unspecified,
+        }
+    }
+
+    /// Serves the file from the given directory instead of the static
+    /// files, such as the `frontend/dist/` a bundler builds into.
+    pub fn with_dir(mut self, dir: &str) -> SpaFallback {
+        self.dir = Some(String::from(dir));
+        self
+    }
+
+    /// Never answers requests under the given prefix, such as `/api`, with
+    /// the fallback file.
+    pub fn with_excluded_prefix(mut self, prefix: &str) -> SpaFallback {
+        self.excluded_prefixes.push(normalize_prefix(prefix));
+        self
+    }
+
+    /// Sets the cache policy for the fallback file. `CachePolicy::NoCache`
+    /// suits an `index.html` pointing at fingerprinted bundles, so a new
+    /// release is picked up straight away.
+    pub fn with_cache_policy(mut self, cache_policy: CachePolicy) -> SpaFallback {
+        self.cache_policy = cache_policy;
+        self
+    }
+
+    pub fn get_file(&self) -> String {
+        self.file.clone()
+    }
+
+    pub fn get_directory(&self) -> Option<String> {
+        self.dir.clone()
+    }
+
+    /// Returns the excluded prefixes in the format `/api` (no trailing slash)
+    pub fn get_excluded_prefixes(&self) -> Vec<String> {
+        self.excluded_prefixes.clone()
+    }
+
+    pub fn get_cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Returns true if the request is for a page the fallback should answer:
+    /// a `GET` or `HEAD` asking for HTML outside the excluded prefixes.
+    pub fn applies_to(&self, request: &Request) -> bool {
+        let method = request.get_method();
+        if (method != "GET" && method != "HEAD") || !request.accepts_html() {
+            return false;
+        }
+        let path = format!("{}{}", request.get_mount_point(), request.get_path());
+        !self.excluded_prefixes.iter().any(|prefix| is_under_prefix(&path, prefix))
+    }
+}
+
+/// Answers a request that matched no route or static file with the route
+/// table's SPA fallback file. Returns None if there is no fallback, it
+/// doesn't apply to the request or its file can't be found.
+pub fn spa_fallback(request: &Request, config: &Configuration) -> Option<Response> {
+    let fallback = config.routes.get_spa_fallback()?;
+    if !fallback.applies_to(request) {
+        return None;
+    }
+    let files = match fallback.dir {
+        Some(ref dir) => Arc::new(DirectoryFiles::new(dir)),
+        None => default_static_files(config),
+    };
+    let options = config.server.get_static_options();
+    let found = clean_static_path(&fallback.file, &options)
+        .and_then(|path| files.metadata(&path, &options).map(|info| (path, info)));
+    match found {
+        Ok((path, ref info)) if !info.is_dir() => {
+            let response = file_response(request.clone(), config, &*files, path, info.clone());
+            Some(with_cache_policy(response, fallback.cache_policy))
+        },
+        _ => {
+            config.log(Level::Warn, "Could not find SPA fallback file", &[("file", &files.describe(&fallback.file))]);
+            None
+        },
+    }
+}
+
+/// What browsers and proxies are told about caching the files a
+/// `StaticMount` serves, through the `Cache-Control` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    /// Sends no `Cache-Control` header, leaving it to the browser.
+    #[default]
+    Unspecified,
+    /// Files may be cached, but must be checked with the server, using
+    /// their ETag, every time they are used.
+    NoCache,
+    /// Files must not be cached at all.
+    NoStore,
+    /// Files may be used without checking with the server for this long.
+    MaxAge(Duration),
+    /// Files never change at their URL, such as fingerprinted bundles like
+    /// `app.3f2a1c.js`, and may be used without checking for this long.
+    Immutable(Duration),
+}
+
+impl CachePolicy {
+    /// Returns the `Cache-Control` header value for this policy, if any.
+    pub fn header_value(&self) -> Option<String> {
+        match *self {
+            CachePolicy::Unspecified => None,
+            CachePolicy::NoCache => Some(String::from("no-cache")),
+            CachePolicy::NoStore => Some(String::from("no-store")),
+            CachePolicy::MaxAge(age) => Some(format!("public, max-age={}", age.as_secs())),
+            CachePolicy::Immutable(age) => Some(format!("public, max-age={}, immutable", age.as_secs())),
+        }
+    }
+}
+
+/// What the static file server may serve besides plain files inside the
+/// static directory. All of these are refused by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StaticOptions {
+    /// Serve files reached through symlinks that point outside the static
+    /// directory.
+    pub follow_symlinks: bool,
+    /// Serve files and directories whose names start with a `.`, such as
+    /// `.env` or `.git/`.
+    pub serve_dotfiles: bool,
+    /// List the contents of directories without an `index.html`, as an HTML
+    /// page or as JSON for clients that prefer it.
+    pub directory_listing: bool,
+}
+
+/// Turns the part of a URL naming a static file into a relative path, such
+/// as `css/app.css`, that can be looked up in a `FileSystem`. The URL path
+/// is percent-decoded once, and anything that could name a file outside
+/// the static files is refused with a 400: `..` segments, backslashes, NUL
+/// bytes and escapes left over after decoding such as the `%2e` of a
+/// double-encoded `%252e`. Hidden files are refused with a 404 unless
+/// `serve_dotfiles` is set.
+pub fn clean_static_path(url_path: &str, options: &StaticOptions) -> Result<String, HttpError> {
+    let decoded = http::url_decode(url_path, false);
+    let lowered = decoded.to_lowercase();
+    if decoded.contains('\0') || decoded.contains('\\')
+        || lowered.contains("%2e") || lowered.contains("%2f") || lowered.contains("%5c") {
+        return Err(HttpError::new(400, "path contains encoded or illegal characters"));
+    }
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(HttpError::new(400, "path leaves the static directory")),
+            _ if segment.starts_with('.') && !options.serve_dotfiles => {
+                return Err(HttpError::new(404, "hidden files are not served"));
+            },
+            _ => segments.push(segment),
+        }
+    }
+    Ok(segments.join("/"))
+}
+
+/// Turns the part of a URL naming a static file into a path inside `root`,
+/// checking it with `clean_static_path`. The resolved path is
+/// canonicalized, following any symlinks, and refused with a 404 if it
+/// doesn't exist or has ended up outside the canonical `root`, unless
+/// `follow_symlinks` is set.
+pub fn resolve_static_path(root: &str, url_path: &str, options: &StaticOptions) -> Result<PathBuf, HttpError> {
+    let path = clean_static_path(url_path, options)?;
+    DirectoryFiles::new(root).resolve(&path, options)
+}
+
+/// Builds a strong ETag for a file from its size and modification time,
+/// such as `"1f4-39d5c2b8"`, so it changes whenever the file is rewritten.
+pub fn file_etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata.modified()
+        .map(|time| DateTime::from_system_time(time).to_unix())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// Returns true if the request's conditional headers show that the client
+/// already has the current version of a resource, which should then be
+/// answered with a `304 Not Modified`. `If-None-Match` is compared with the
+/// ETag using the weak comparison, and `If-Modified-Since` is only looked
+/// at when there is no `If-None-Match`, as RFC 7232 requires.
+pub fn is_not_modified(request: &Request, etag: Option<&str>, last_modified: Option<DateTime>) -> bool {
+    if let Some(if_none_match) = request.get_header("If-None-Match") {
+        let etag = match etag {
+            Some(etag) => etag.trim_start_matches("W/"),
+            None => return false,
+        };
+        return if_none_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    match (request.get_header("If-Modified-Since"), last_modified) {
+        (Some(since), Some(last_modified)) => DateTime::parse_http_date(&since)
+            .map(|since| last_modified <= since)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// The most ranges a single `Range` header may ask for. Longer headers are
+/// ignored and the whole file is sent instead.
+pub const MAX_RANGES: usize = 16;
+
+/// Works out which bytes of a `length` byte resource a request asks for
+/// with its `Range` header, as inclusive `(first, last)` offsets. Returns
+/// None if the whole resource should be sent: there is no `Range` header,
+/// it isn't a valid `bytes` range, it asks for more than `MAX_RANGES`
+/// ranges, or an `If-Range` header doesn't match the resource's strong
+/// ETag or exact `Last-Modified` date. Returns an empty Vec if none of the
+/// ranges are satisfiable, which should be answered with a 416.
+pub fn requested_ranges(request: &Request, length: u64, etag: Option<&str>, last_modified: Option<DateTime>)
+                        -> Option<Vec<(u64, u64)>> {
+    let header = request.get_header("Range")?;
+    if let Some(if_range) = request.get_header("If-Range") {
+        let if_range = if_range.trim();
+        let current = if if_range.starts_with('"') {
+            etag.map(|etag| etag == if_range).unwrap_or(false)
+        } else {
+            match (DateTime::parse_http_date(if_range), last_modified) {
+                (Some(date), Some(last_modified)) => date == last_modified,
+                _ => false,
+            }
+        };
+        if !current {
+            return None;
+        }
+    }
+    let (unit, specs) = header.split_at(header.find('=')?);
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let specs: Vec<&str> = specs[1..].split(',').map(|spec| spec.trim()).collect();
+    if specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let dash = spec.find('-')?;
+        let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+        if first.is_empty() {
+            // A suffix range, such as `-500` for the last 500 bytes.
+            let suffix: u64 = last.parse().ok()?;
+            if suffix > 0 && length > 0 {
+                ranges.push((length.saturating_sub(suffix), length - 1));
+            }
+            continue;
+        }
+        let first: u64 = first.parse().ok()?;
+        let last = if last.is_empty() { u64::MAX } else { last.parse().ok()? };
+        if last < first {
+            return None;
+        }
+        if first < length {
+            ranges.push((first, last.min(length - 1)));
+        }
+    }
+    Some(ranges)
+}
+
+/// Turns a 200 Response holding a whole resource into a
+/// `206 Partial Content` holding just the given ranges, as returned by
+/// `requested_ranges`. A single range is sent as is with a `Content-Range`
+/// header, and several are sent as a `multipart/byteranges` body. A
+/// streaming Response is read into memory first. Fails with a 416 if there
+/// are no ranges or any of them doesn't fit inside the body, and with a 500
+/// if a streaming body can't be read.
+pub fn partial_response(response: Response, ranges: &[(u64, u64)]) -> Result<Response, HttpError> {
+    let response = response.buffered()?;
+    let body = response.get_body();
+    let length = body.len() as u64;
+    if ranges.is_empty() || ranges.iter().any(|&(first, last)| first > last || last >= length) {
+        return Err(HttpError::new(416, "Requested range not satisfiable"));
+    }
+    let open_range = |first: u64, last: u64| -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::Cursor::new(body[first as usize..=last as usize].to_vec())))
+    };
+    Ok(ranged_response(response, length, ranges, open_range).and_then(Response::buffered)?)
+}
+
+/// Builds a `206 Partial Content` streaming the given ranges of a `length`
+/// byte resource, each read from the reader `open_range(first, last)`
+/// returns.
+fn ranged_response<F>(response: Response, length: u64, ranges: &[(u64, u64)], mut open_range: F) -> io::Result<Response>
+    where F: FnMut(u64, u64) -> io::Result<Box<dyn Read + Send>> {
+    let content_range = |first: u64, last: u64| format!("bytes {}-{}/{}", first, last, length);
+    if ranges.len() == 1 {
+        let (first, last) = ranges[0];
+        return Ok(response.with_status(206)
+            .with_header("Content-Range", &content_range(first, last))
+            .with_stream(open_range(first, last)?, Some(last - first + 1)));
+    }
+    let boundary = http::generate_request_id().replace('-', "");
+    let content_type = response.get_content_type().stringify();
+    let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+    let mut body_length = 0;
+    for &(first, last) in ranges {
+        let part_headers = format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                                   boundary, content_type, content_range(first, last));
+        body_length += part_headers.len() as u64 + last - first + 1;
+        body = Box::new(body.chain(io::Cursor::new(part_headers.into_bytes())).chain(open_range(first, last)?));
+    }
+    let end = format!("\r\n--{}--\r\n", boundary);
+    body_length += end.len() as u64;
+    body = Box::new(body.chain(io::Cursor::new(end.into_bytes())));
+    Ok(response.with_status(206)
+        .with_content_type(ContentType::MultipartByteranges)
+        .with_header("Content-Type", &format!("multipart/byteranges; boundary={}", boundary))
+        .with_stream(body, Some(body_length)))
+}
+
+/// Lists the files and directories at `dir_path` in `files`, which was
+/// requested as `path`. Anything `clean_static_path` or the FileSystem
+/// would refuse to serve is left out. The listing is JSON, as
+/// `{"path": "/static/docs/", "entries": [{"name": "a.txt", "type": "file", "size": 7, "modified": "2000-10-10T13:55:36Z"}]}`,
+/// for clients that prefer it and an HTML table otherwise.
+fn directory_listing(request: &Request, files: &dyn FileSystem, dir_path: &str, path: &str,
+                     options: &StaticOptions) -> Response {
+    let mut entries: Vec<(bool, String, u64, Option<DateTime>)> = Vec::new();
+    for name in files.read_dir(dir_path, options).unwrap_or_default() {
+        let info = clean_static_path(&join_path(dir_path, &http::url_encode(&name)), options)
+            .and_then(|entry_path| files.metadata(&entry_path, options));
+        if let Ok(info) = info {
+            entries.push((info.is_dir(), name, info.get_len(), info.get_modified()));
+        }
+    }
+    // Directories first, then by name.
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    if request.prefers_json() {
+        let entries: Vec<JsonValue> = entries.into_iter().map(|(is_dir, name, size, modified)| {
+            JsonValue::object(vec![
+                ("name", JsonValue::from(name)),
+                ("type", JsonValue::from(if is_dir { "directory" } else { "file" })),
+                ("size", if is_dir { JsonValue::Null } else { JsonValue::from(size) }),
+                ("modified", JsonValue::from(modified.map(|modified| modified.to_iso8601()))),
+            ])
+        }).collect();
+        let mut body = BTreeMap::new();
+        body.insert(String::from("path"), JsonValue::from(path));
+        body.insert(String::from("entries"), JsonValue::from(entries));
+        return http::ok(JsonValue::Object(body).stringify(), ContentType::ApplicationJson);
+    }
+    let title = http::escape_html(path);
+    let mut html = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+                            <body><h1>Index of {0}</h1>\n<table>\n\
+                            <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n", title);
+    if !dir_path.is_empty() {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for (is_dir, name, size, modified) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        let size = if is_dir { String::from("-") } else { size.to_string() };
+        let modified = modified.map(|modified| modified.to_http_date()).unwrap_or_default();
+        html.push_str(&format!("<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                               http::url_encode(&name), slash, http::escape_html(&name), slash, size, modified));
+    }
+    html.push_str("</table>\n</body></html>\n");
+    http::ok(html, ContentType::TextHtml)
+}
+
+#[cfg(test)]
+fn static_fixture(name: &str) -> PathBuf {
+    let root = ::std::env::temp_dir().join(format!("servo_static_{}_{}", name, ::std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("public/css")).unwrap();
+    fs::write(root.join("public/css/app.css"), "body {}").unwrap();
+    fs::write(root.join("public/.env"), "SECRET=1").unwrap();
+    fs::write(root.join("secret.txt"), "secret").unwrap();
+    root
+}
+
+#[test]
+fn test_resolve_static_path_refuses_traversal() {
+    let root = static_fixture("traversal");
+    let public = format!("{}/", root.join("public").display());
+    let options = StaticOptions::default();
+    let status = |url: &str| resolve_static_path(&public, url, &options).map_err(|e| e.get_status());
+    assert_eq!(status("css/app.css"), Ok(root.join("public/css/app.css").canonicalize().unwrap()));
+    assert_eq!(status("./css//app.css"), Ok(root.join("public/css/app.css").canonicalize().unwrap()));
+    assert_eq!(status("../secret.txt"), Err(400));
+    assert_eq!(status("css/../../secret.txt"), Err(400));
+    assert_eq!(status("%2e%2e/secret.txt"), Err(400));
+    assert_eq!(status("%2E%2E%2Fsecret.txt"), Err(400));
+    assert_eq!(status("%252e%252e%252fsecret.txt"), Err(400));
+    assert_eq!(status("..%5csecret.txt"), Err(400));
+    assert_eq!(status("..\\secret.txt"), Err(400));
+    assert_eq!(status("css/app.css%00.png"), Err(400));
+    assert_eq!(status("missing.css"), Err(404));
+    assert_eq!(status(".env"), Err(404));
+    assert_eq!(status("%2eenv"), Err(404));
+    let dotfiles = StaticOptions { serve_dotfiles: true, ..StaticOptions::default() };
+    assert!(resolve_static_path(&public, ".env", &dotfiles).is_ok());
+    assert_eq!(resolve_static_path(&public, "../secret.txt", &dotfiles).map_err(|e| e.get_status()), Err(400));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolve_static_path_symlink_escapes() {
+    use std::os::unix::fs::symlink;
+    let root = static_fixture("symlinks");
+    symlink(root.join("secret.txt"), root.join("public/secret.txt")).unwrap();
+    symlink(root.join("public/css/app.css"), root.join("public/app.css")).unwrap();
+    let public = format!("{}/", root.join("public").display());
+    let status = |url: &str, options: StaticOptions| resolve_static_path(&public, url, &options).map_err(|e| e.get_status());
+    assert_eq!(status("secret.txt", StaticOptions::default()), Err(404));
+    assert!(status("app.css", StaticOptions::default()).is_ok());
+    let follow = StaticOptions { follow_symlinks: true, ..StaticOptions::default() };
+    assert_eq!(status("secret.txt", follow), Ok(root.join("secret.txt").canonicalize().unwrap()));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_static_route_refuses_traversal() {
+    use super::{Server, route_request};
+    let root = static_fixture("route");
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&format!("{}/", root.join("public").display())));
+    let get = |route: &str| route_request(Request::from(format!("GET {} HTTP/1.1\r\n\r\n", route).as_str()), &configs);
+    let resp = get("/static/css/app.css");
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.buffered().unwrap().get_body(), b"body {}".to_vec());
+    assert_eq!(get("/static/..%2fsecret.txt").get_status(), 400);
+    assert_eq!(get("/static/%252e%252e%252fsecret.txt").get_status(), 400);
+    assert_eq!(get("/static/.env").get_status(), 404);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_static_route_conditional_get() {
+    use super::{Server, route_request};
+    let root = static_fixture("conditional");
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&format!("{}/", root.join("public").display())));
+    let get = |headers: &str| route_request(
+        Request::from(format!("GET /static/css/app.css HTTP/1.1\r\n{}\r\n", headers).as_str()), &configs);
+    let resp = get("");
+    assert_eq!(resp.get_status(), 200);
+    let headers = resp.get_headers().unwrap();
+    let etag = headers["ETag"].clone();
+    let last_modified = headers["Last-Modified"].clone();
+    assert!(etag.starts_with("\"7-"));
+    assert!(DateTime::parse_http_date(&last_modified).is_some());
+
+    let resp = get(&format!("If-None-Match: \"other\", {}\r\n", etag));
+    assert_eq!(resp.get_status(), 304);
+    assert!(resp.get_body().is_empty());
+    assert_eq!(resp.get_headers().unwrap()["ETag"], etag);
+    assert_eq!(get(&format!("If-None-Match: W/{}\r\n", etag)).get_status(), 304);
+    assert_eq!(get("If-None-Match: *\r\n").get_status(), 304);
+    assert_eq!(get("If-None-Match: \"other\"\r\n").get_status(), 200);
+
+    assert_eq!(get(&format!("If-Modified-Since: {}\r\n", last_modified)).get_status(), 304);
+    assert_eq!(get("If-Modified-Since: Sat, 01 Jan 2000 00:00:00 GMT\r\n").get_status(), 200);
+    assert_eq!(get("If-Modified-Since: not a date\r\n").get_status(), 200);
+    assert_eq!(get(&format!("If-None-Match: \"other\"\r\nIf-Modified-Since: {}\r\n", last_modified)).get_status(), 200);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_requested_ranges() {
+    let request = |headers: &str| Request::from(format!("GET /static/a.txt HTTP/1.1\r\n{}\r\n", headers).as_str());
+    let ranges = |header: &str| requested_ranges(&request(&format!("Range: {}\r\n", header)), 100, None, None);
+    assert_eq!(requested_ranges(&request(""), 100, None, None), None);
+    assert_eq!(ranges("bytes=0-9"), Some(vec![(0, 9)]));
+    assert_eq!(ranges("bytes=90-"), Some(vec![(90, 99)]));
+    assert_eq!(ranges("bytes=-10"), Some(vec![(90, 99)]));
+    assert_eq!(ranges("bytes=-500"), Some(vec![(0, 99)]));
+    assert_eq!(ranges("bytes=50-500"), Some(vec![(50, 99)]));
+    assert_eq!(ranges("bytes=0-0, 10-19,-1"), Some(vec![(0, 0), (10, 19), (99, 99)]));
+    assert_eq!(ranges("bytes=100-200, 150-"), Some(vec![]));
+    assert_eq!(ranges("bytes=-0"), Some(vec![]));
+    assert_eq!(ranges("bytes=100-200, 0-1"), Some(vec![(0, 1)]));
+    assert_eq!(ranges("bytes=10-5"), None);
+    assert_eq!(ranges("bytes=a-b"), None);
+    assert_eq!(ranges("items=0-9"), None);
+    assert_eq!(ranges("bytes 0-9"), None);
+    assert_eq!(ranges(&format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","))), None);
+
+    let modified = DateTime::from_unix(971_186_136);
+    let conditional = |if_range: &str| requested_ranges(
+        &request(&format!("Range: bytes=0-9\r\nIf-Range: {}\r\n", if_range)), 100, Some("\"64-1\""), Some(modified));
+    assert_eq!(conditional("\"64-1\""), Some(vec![(0, 9)]));
+    assert_eq!(conditional("\"64-2\""), None);
+    assert_eq!(conditional("W/\"64-1\""), None);
+    assert_eq!(conditional("Tue, 10 Oct 2000 13:55:36 GMT"), Some(vec![(0, 9)]));
+    assert_eq!(conditional("Tue, 10 Oct 2000 13:55:37 GMT"), None);
+}
+
+#[test]
+fn test_static_route_byte_ranges() {
+    use super::{Server, route_request};
+    let root = static_fixture("ranges");
+    fs::write(root.join("public/digits.txt"), "0123456789").unwrap();
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&format!("{}/", root.join("public").display())));
+    let get = |headers: &str| route_request(
+        Request::from(format!("GET /static/digits.txt HTTP/1.1\r\n{}\r\n", headers).as_str()), &configs)
+        .buffered().unwrap();
+    let resp = get("");
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.get_headers().unwrap()["Accept-Ranges"], "bytes");
+
+    let resp = get("Range: bytes=2-4\r\n");
+    assert_eq!(resp.get_status(), 206);
+    assert_eq!(resp.get_body(), b"234".to_vec());
+    assert_eq!(resp.get_headers().unwrap()["Content-Range"], "bytes 2-4/10");
+
+    let resp = get("Range: bytes=0-1,-2\r\n");
+    assert_eq!(resp.get_status(), 206);
+    let content_type = resp.get_headers().unwrap()["Content-Type"].clone();
+    let boundary = content_type.trim_start_matches("multipart/byteranges; boundary=");
+    assert_ne!(boundary, content_type);
+    let expected = format!("\r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                            \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                            \r\n--{0}--\r\n", boundary);
+    assert_eq!(String::from_utf8(resp.get_body()).unwrap(), expected);
+    let head = String::from_utf8(resp.byteify()).unwrap();
+    assert!(head.contains(&format!("Content-Type: multipart/byteranges; boundary={}", boundary)));
+    assert!(head.contains(&format!("Content-Length: {}", expected.len())));
+
+    let resp = get("Range: bytes=10-\r\n");
+    assert_eq!(resp.get_status(), 416);
+    assert_eq!(resp.get_headers().unwrap()["Content-Range"], "bytes */10");
+    assert_eq!(get("Range: bytes=2-4\r\nIf-Range: \"stale\"\r\n").get_body(), b"0123456789".to_vec());
+
+    let digits = || http::ok(String::from("0123456789"), ContentType::TextPlain);
+    let resp = partial_response(digits(), &[(2, 4)]).unwrap();
+    assert_eq!((resp.get_status(), resp.is_streaming()), (206, false));
+    assert_eq!(resp.get_body(), b"234".to_vec());
+    let streamed = Response::new().with_status(200).with_stream(io::Cursor::new(b"0123456789".to_vec()), None);
+    assert_eq!(partial_response(streamed, &[(8, 9)]).unwrap().get_body(), b"89".to_vec());
+    assert_eq!(partial_response(digits(), &[(2, 10)]).unwrap_err().get_status(), 416);
+    assert_eq!(partial_response(digits(), &[(5, 4)]).unwrap_err().get_status(), 416);
+    assert_eq!(partial_response(digits(), &[]).unwrap_err().get_status(), 416);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_static_route_directories() {
+    use super::{Server, route_request};
+    let root = static_fixture("directories");
+    fs::create_dir_all(root.join("public/docs/guide")).unwrap();
+    fs::write(root.join("public/docs/guide/index.html"), "<h1>Guide</h1>").unwrap();
+    fs::write(root.join("public/docs/a <b>.txt"), "a").unwrap();
+    fs::write(root.join("public/docs/.secret"), "hidden").unwrap();
+    let public = format!("{}/", root.join("public").display());
+    let configs = Configuration::new().with_server_configurations(Server::new().with_static_dir(&public));
+    let get = |configs: &Configuration, route: &str, accept: &str| route_request(
+        Request::from(format!("GET {} HTTP/1.1\r\nAccept: {}\r\n\r\n", route, accept).as_str()), configs)
+        .buffered().unwrap();
+
+    let resp = get(&configs, "/static/docs/guide?lang=en", "text/html");
+    assert_eq!(resp.get_status(), 301);
+    assert_eq!(resp.get_headers().unwrap()["Location"], "/static/docs/guide/?lang=en");
+    let resp = get(&configs, "/static/docs/guide/", "text/html");
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.get_body(), b"<h1>Guide</h1>".to_vec());
+    assert_eq!(get(&configs, "/static/docs/", "text/html").get_status(), 404);
+
+    let listing = configs.clone().with_server_configurations(Server::new().with_static_dir(&public)
+        .with_static_options(StaticOptions { directory_listing: true, ..StaticOptions::default() }));
+    let resp = get(&listing, "/static/docs/", "text/html");
+    assert_eq!(resp.get_status(), 200);
+    let html = String::from_utf8(resp.get_body()).unwrap();
+    assert!(html.contains("<title>Index of /static/docs/</title>"));
+    assert!(html.contains("<a href=\"../\">../</a>"));
+    assert!(html.contains("<a href=\"guide/\">guide/</a>"));
+    assert!(html.contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a></td><td>1</td>"));
+    assert!(!html.contains("secret"));
+    assert!(html.find("guide/").unwrap() < html.find("a &lt;b&gt;.txt").unwrap());
+
+    let resp = get(&listing, "/static/docs/", "application/json");
+    let body = JsonValue::parse(&String::from_utf8(resp.get_body()).unwrap()).unwrap();
+    assert_eq!(body.get("path").and_then(|p| p.as_str()), Some("/static/docs/"));
+    let entries = body.get("entries").and_then(|e| e.as_array()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].get("type").and_then(|t| t.as_str()), Some("directory"));
+    assert!(entries[0].get("size").unwrap().is_null());
+    assert_eq!(entries[1].get("name").and_then(|n| n.as_str()), Some("a <b>.txt"));
+    assert_eq!(entries[1].get("size").and_then(|s| s.as_f64()), Some(1.0));
+    assert!(entries[1].get("modified").and_then(|m| m.as_str()).unwrap().ends_with('Z'));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_static_mounts() {
+    use super::{Routes, Server, route_request};
+    let root = static_fixture("mounts");
+    fs::create_dir_all(root.join("build")).unwrap();
+    fs::write(root.join("build/app.3f2a1c.js"), "run()").unwrap();
+    let public = format!("{}/", root.join("public").display());
+    let routes = Routes::new()
+        .with_static_mount(StaticMount::new("static", "/assets/").with_cache_policy(CachePolicy::NoCache))
+        .with_static_mount(StaticMount::new("build", "/build")
+            .with_dir(&format!("{}/", root.join("build").display()))
+            .with_cache_policy(CachePolicy::Immutable(Duration::from_secs(31_536_000))));
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_domain("example.com").with_port("8000").with_static_dir(&public))
+        .with_routes(routes);
+    let get = |route: &str| route_request(Request::from(format!("GET {} HTTP/1.1\r\n\r\n", route).as_str()), &configs)
+        .buffered().unwrap();
+
+    let resp = get("/assets/css/app.css");
+    assert_eq!(resp.get_body(), b"body {}".to_vec());
+    assert_eq!(resp.get_headers().unwrap()["Cache-Control"], "no-cache");
+    assert_eq!(get("/static/css/app.css").get_status(), 404);
+    let resp = get("/build/app.3f2a1c.js");
+    assert_eq!(resp.get_body(), b"run()".to_vec());
+    assert_eq!(resp.get_headers().unwrap()["Cache-Control"], "public, max-age=31536000, immutable");
+    let resp = get("/build/missing.js");
+    assert_eq!(resp.get_status(), 404);
+    assert!(resp.get_headers().map(|headers| !headers.contains_key("Cache-Control")).unwrap_or(true));
+
+    assert_eq!(configs.get_static_uri(), "http://example.com:8000/assets/");
+    assert_eq!(configs.get_static_uri_for("build"), Some(String::from("http://example.com:8000/build/")));
+    assert_eq!(configs.get_static_uri_for("missing"), None);
+    assert_eq!(Configuration::new().with_server_configurations(Server::new().with_domain("example.com").with_port("8000"))
+                   .get_static_uri(), "http://example.com:8000/static/");
+    let nested = Configuration::new().with_routes(Routes::new()
+        .with_mount("/docs", Routes::new().with_static_mount(StaticMount::new("manual", "/manual"))));
+    assert!(nested.get_static_uri_for("manual").unwrap().ends_with("/docs/manual/"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_static_route_precompressed() {
+    use super::{Server, route_request};
+    let root = static_fixture("precompressed");
+    fs::write(root.join("public/css/app.css.gz"), "gzipped").unwrap();
+    fs::write(root.join("public/css/app.css.br"), "brotli").unwrap();
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&format!("{}/", root.join("public").display())));
+    let get = |route: &str, accept: &str| route_request(
+        Request::from(format!("GET {} HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", route, accept).as_str()), &configs)
+        .buffered().unwrap();
+    let header = |resp: &Response, name: &str| resp.get_headers().and_then(|headers| headers.get(name).cloned());
+
+    let resp = get("/static/css/app.css", "gzip, deflate, br");
+    assert_eq!(resp.get_body(), b"brotli".to_vec());
+    assert_eq!(header(&resp, "Content-Encoding"), Some(String::from("br")));
+    assert_eq!(header(&resp, "Vary"), Some(String::from("Accept-Encoding")));
+    assert_eq!(resp.get_content_type().stringify(), "text/css");
+    assert!(String::from_utf8(resp.byteify()).unwrap().contains("Content-Type: text/css"));
+    let brotli_etag = header(&get("/static/css/app.css", "br"), "ETag").unwrap();
+    assert!(brotli_etag.ends_with("-br\""));
+
+    let resp = get("/static/css/app.css", "gzip, br;q=0.5");
+    assert_eq!(resp.get_body(), b"gzipped".to_vec());
+    assert_eq!(header(&resp, "Content-Encoding"), Some(String::from("gzip")));
+    assert_ne!(header(&resp, "ETag"), Some(brotli_etag));
+
+    let resp = get("/static/css/app.css", "identity, gzip;q=0");
+    assert_eq!(resp.get_body(), b"body {}".to_vec());
+    assert_eq!(header(&resp, "Content-Encoding"), None);
+    assert_eq!(header(&resp, "Vary"), Some(String::from("Accept-Encoding")));
+
+    fs::write(root.join("public/plain.txt"), "plain").unwrap();
+    let resp = get("/static/plain.txt", "gzip");
+    assert_eq!((header(&resp, "Content-Encoding"), header(&resp, "Vary")), (None, None));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_spa_fallback() {
+    use super::{Routes, Server, route_request};
+    let root = static_fixture("spa");
+    fs::write(root.join("public/index.html"), "<div id=\"app\"></div>").unwrap();
+    let public = format!("{}/", root.join("public").display());
+    let routes = Routes::new()
+        .with_route("GET /api/users", |_: Request, _: &Configuration| "[]")
+        .with_spa_fallback(SpaFallback::new("index.html")
+            .with_excluded_prefix("/api/")
+            .with_cache_policy(CachePolicy::NoCache));
+    assert_eq!(routes.get_spa_fallback().unwrap().get_excluded_prefixes(), vec!["/api"]);
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&public))
+        .with_routes(routes);
+    let request = |method: &str, route: &str, accept: &str| route_request(
+        Request::from(format!("{} {} HTTP/1.1\r\nAccept: {}\r\n\r\n", method, route, accept).as_str()), &configs)
+        .buffered().unwrap();
+    let page = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+    let resp = request("GET", "/dashboard/settings?tab=1", page);
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.get_body(), b"<div id=\"app\"></div>".to_vec());
+    assert_eq!(resp.get_headers().unwrap()["Cache-Control"], "no-cache");
+    assert_eq!(request("GET", "/static/missing.js", page).get_status(), 200);
+    assert_eq!(request("GET", "/static/css/app.css", page).get_body(), b"body {}".to_vec());
+    assert_eq!(request("GET", "/api/users", page).get_body(), b"[]".to_vec());
+    assert_eq!(request("GET", "/api/missing", page).get_status(), 404);
+    assert_eq!(request("GET", "/api", page).get_status(), 404);
+    assert_eq!(request("GET", "/dashboard", "application/json").get_status(), 404);
+    assert_eq!(request("GET", "/static/missing.js", "*/*").get_status(), 404);
+    assert_eq!(request("POST", "/dashboard", page).get_status(), 404);
+    assert_eq!(request("GET", "/static/.env", page).get_status(), 200);
+    assert_eq!(request("GET", "/static/../secret.txt", page).get_status(), 400);
+
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new().with_static_dir(&public))
+        .with_routes(Routes::new().with_spa_fallback(SpaFallback::new("app.html")));
+    let resp = route_request(Request::from(format!("GET /dashboard HTTP/1.1\r\nAccept: {}\r\n\r\n", page).as_str()), &configs);
+    assert_eq!(resp.get_status(), 404);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,490 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use http::HttpError;
+use http::date::DateTime;
+use static_files::{StaticOptions, file_etag};
+
+/// What a FileSystem knows about one of its files or directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    is_dir: bool,
+    len: u64,
+    modified: Option<DateTime>,
+    etag: Option<String>,
+}
+
+impl FileInfo {
+    /// Describes a file `len` bytes long with the given ETag, such as
+    /// `"1f4-39d5c2b8"` including the quotes.
+    pub fn file(len: u64, etag: &str, modified: Option<DateTime>) -> FileInfo {
+        FileInfo {
+            is_dir: false,
+            len,
+            modified,
+            etag: Some(String::from(etag)),
+        }
+    }
+
+    pub fn directory(modified: Option<DateTime>) -> FileInfo {
+        FileInfo {
+            is_dir: true,
+            len: 0,
+            modified,
+            etag: None,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn get_len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn get_modified(&self) -> Option<DateTime> {
+        self.modified
+    }
+
+    /// Returns the ETag of a file, None for directories.
+    pub fn get_etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+}
+
+/// Somewhere static files and HTML templates are read from, such as a
+/// directory on disk (`DirectoryFiles`) or files compiled into the binary
+/// (`EmbeddedFiles`). Paths are relative and `/` separated, such as
+/// `css/app.css`, with an empty path for the top directory. Static file
+/// paths have already been percent-decoded and checked by
+/// `static_files::clean_static_path` when they get here, so `..` segments
+/// never reach `metadata`.
+pub trait FileSystem: Send + Sync {
+    /// Looks up a file or directory, failing with a 404 if it doesn't exist
+    /// or `options` say it can't be served.
+    fn metadata(&self, path: &str, options: &StaticOptions) -> Result<FileInfo, HttpError>;
+
+    /// Opens a file to be read starting `offset` bytes in, failing if
+    /// `options` say it can't be served.
+    fn open(&self, path: &str, offset: u64, options: &StaticOptions) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Returns the names of the entries in a directory, failing if `options`
+    /// say it can't be served.
+    fn read_dir(&self, path: &str, options: &StaticOptions) -> io::Result<Vec<String>>;
+
+    /// Describes where a path is for log messages, such as
+    /// `static/css/app.css`.
+    fn describe(&self, path: &str) -> String {
+        String::from(path)
+    }
+}
+
+/// Files in a directory on disk, such as the default `static/`. Paths are
+/// refused if they lead outside the directory through symlinks, unless
+/// `StaticOptions::follow_symlinks` is set. The check is made on the
+/// canonical path, which is then opened by name, so a symlink swapped in
+/// between the check and the open is still followed. The directory's own
+/// canonical path is looked up the first time it is needed and kept.
+#[derive(Debug, Clone)]
+pub struct DirectoryFiles {
+    root: String,
+    canonical_root: OnceLock<PathBuf>,
+}
+
+impl PartialEq for DirectoryFiles {
+    fn eq(&self, other: &DirectoryFiles) -> bool {
+        self.root == other.root
+    }
+}
+
+impl Eq for DirectoryFiles {}
+
+impl DirectoryFiles {
+    pub fn new(root: &str) -> DirectoryFiles {
+        DirectoryFiles {
+            root: String::from(root),
+            canonical_root: OnceLock::new(),
+        }
+    }
+
+    pub fn get_root(&self) -> String {
+        self.root.clone()
+    }
+
+    /// Returns where a relative path is on disk.
+    fn full_path(&self, path: &str) -> PathBuf {
+        Path::new(&self.root).join(path.trim_start_matches('/'))
+    }
+
+    /// Canonicalizes a path under the directory, following any symlinks,
+    /// and refuses it with a 404 if it doesn't exist or has ended up outside
+    /// the canonical directory, unless `follow_symlinks` is set.
+    pub fn resolve(&self, path: &str, options: &StaticOptions) -> Result<PathBuf, HttpError> {
+        let canonical_root = self.canonical_root()?;
+        let canonical = fs::canonicalize(self.full_path(path))
+            .map_err(|_| HttpError::new(404, "file does not exist"))?;
+        if !canonical.starts_with(canonical_root) && !options.follow_symlinks {
+            return Err(HttpError::new(404, "symlink leads outside the static directory"));
+        }
+        Ok(canonical)
+    }
+
+    /// Returns the canonical path of the directory, looking it up the first
+    /// time. A directory that doesn't exist yet is looked up again next time.
+    fn canonical_root(&self) -> Result<&PathBuf, HttpError> {
+        if let Some(root) = self.canonical_root.get() {
+            return Ok(root);
+        }
+        let root = fs::canonicalize(&self.root)
+            .map_err(|_| HttpError::new(404, "static directory does not exist"))?;
+        Ok(self.canonical_root.get_or_init(|| root))
+    }
+
+    /// Same as `resolve`, for reading files.
+    fn resolve_io(&self, path: &str, options: &StaticOptions) -> io::Result<PathBuf> {
+        self.resolve(path, options)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.get_message()))
+    }
+}
+
+impl FileSystem for DirectoryFiles {
+    fn metadata(&self, path: &str, options: &StaticOptions) -> Result<FileInfo, HttpError> {
+        let metadata = fs::metadata(self.resolve(path, options)?)
+            .map_err(|_| HttpError::new(404, "file does not exist"))?;
+        let modified = metadata.modified().ok().map(DateTime::from_system_time);
+        if metadata.is_dir() {
+            Ok(FileInfo::directory(modified))
+        } else if metadata.is_file() {
+            Ok(FileInfo::file(metadata.len(), &file_etag(&metadata), modified))
+        } else {
+            Err(HttpError::new(404, "not a file"))
+        }
+    }
+
+    fn open(&self, path: &str, offset: u64, options: &StaticOptions) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = File::open(self.resolve_io(path, options)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+
+    fn read_dir(&self, path: &str, options: &StaticOptions) -> io::Result<Vec<String>> {
+        let entries = fs::read_dir(self.resolve_io(path, options)?)?;
+        Ok(entries.filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect())
+    }
+
+    fn describe(&self, path: &str) -> String {
+        self.full_path(path).display().to_string()
+    }
+}
+
+/// A file compiled into the binary, as listed in the table `write_asset_table`
+/// generates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedFile {
+    path: &'static str,
+    contents: &'static [u8],
+    etag: Cow<'static, str>,
+    modified: Option<i64>,
+}
+
+impl EmbeddedFile {
+    /// Describes a file at `path` with its contents, its ETag and when it
+    /// was last modified in seconds since the Unix epoch.
+    pub const fn new(path: &'static str, contents: &'static [u8], etag: &'static str, modified: Option<i64>) -> EmbeddedFile {
+        EmbeddedFile {
+            path,
+            contents,
+            etag: Cow::Borrowed(etag),
+            modified,
+        }
+    }
+
+    pub fn get_path(&self) -> &'static str {
+        self.path
+    }
+
+    pub fn get_contents(&self) -> &'static [u8] {
+        self.contents
+    }
+
+    pub fn get_etag(&self) -> String {
+        self.etag.to_string()
+    }
+
+    pub fn get_modified(&self) -> Option<DateTime> {
+        self.modified.map(DateTime::from_unix)
+    }
+}
+
+/// Files compiled into the binary and served from memory, so a server can
+/// ship as a single executable. Build the table in a build script with
+/// `write_asset_table`, which works out every ETag at build time, or list
+/// a few files with the `embed_files!` macro. Directories are implied by
+/// the paths of the files in them.
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddedFiles {
+    files: BTreeMap<String, EmbeddedFile>,
+}
+
+impl EmbeddedFiles {
+    pub fn new() -> EmbeddedFiles {
+        EmbeddedFiles::default()
+    }
+
+    /// Builds the file system from a table such as the one
+    /// `write_asset_table` generates.
+    pub fn from_table(table: &[EmbeddedFile]) -> EmbeddedFiles {
+        let mut files = EmbeddedFiles::new();
+        for file in table {
+            files.add_file(file.clone());
+        }
+        files
+    }
+
+    /// Adds a file, working out its ETag from its contents. Used in the
+    /// builder pattern.
+    pub fn with_file(mut self, path: &'static str, contents: &'static [u8]) -> EmbeddedFiles {
+        self.add_file(EmbeddedFile {
+            path,
+            contents,
+            etag: Cow::Owned(content_etag(contents)),
+            modified: None,
+        });
+        self
+    }
+
+    pub fn add_file(&mut self, file: EmbeddedFile) {
+        self.files.insert(String::from(file.path.trim_matches('/')), file);
+    }
+
+    pub fn get_file(&self, path: &str) -> Option<&EmbeddedFile> {
+        self.files.get(path.trim_matches('/'))
+    }
+
+    /// Returns the path of every file, in order.
+    pub fn get_paths(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Returns the paths of the files under a directory, relative to it,
+    /// or None if there is no such directory.
+    fn under(&self, path: &str) -> Option<Vec<&str>> {
+        let prefix = match path.trim_matches('/') {
+            "" => String::new(),
+            path => format!("{}/", path),
+        };
+        let under: Vec<&str> = self.files.keys()
+            .filter(|key| key.starts_with(&prefix))
+            .map(|key| &key[prefix.len()..])
+            .collect();
+        if under.is_empty() { None } else { Some(under) }
+    }
+}
+
+impl FileSystem for EmbeddedFiles {
+    fn metadata(&self, path: &str, _options: &StaticOptions) -> Result<FileInfo, HttpError> {
+        if let Some(file) = self.get_file(path) {
+            return Ok(FileInfo::file(file.contents.len() as u64, &file.etag, file.get_modified()));
+        }
+        match self.under(path) {
+            Some(_) => Ok(FileInfo::directory(None)),
+            None => Err(HttpError::new(404, "file does not exist")),
+        }
+    }
+
+    fn open(&self, path: &str, offset: u64, _options: &StaticOptions) -> io::Result<Box<dyn Read + Send>> {
+        let file = self.get_file(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no embedded file at this path"))?;
+        let mut reader = Cursor::new(file.contents);
+        reader.set_position(offset);
+        Ok(Box::new(reader))
+    }
+
+    fn read_dir(&self, path: &str, _options: &StaticOptions) -> io::Result<Vec<String>> {
+        let under = self.under(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no embedded directory at this path"))?;
+        let mut names: Vec<String> = under.iter()
+            .filter_map(|file| file.split('/').next().map(String::from))
+            .collect();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn describe(&self, path: &str) -> String {
+        format!("embedded:{}", path)
+    }
+}
+
+/// Works out an ETag from a file's contents, such as `"7-af63bd4c8601b7be"`,
+/// so it only changes when the contents do.
+pub fn content_etag(contents: &[u8]) -> String {
+    // 64 bit FNV-1a, which unlike the standard library's hashers gives the
+    // same result in every build.
+    let hash = contents.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("\"{:x}-{:016x}\"", contents.len(), hash)
+}
+
+/// Lists the files in a directory with `include_bytes!` so they are
+/// compiled into the binary, working out their ETags from their contents:
+///
+/// ```ignore
+/// let files = embed_files!("../static/", ["index.html", "css/app.css"]);
+/// ```
+///
+/// The directory is relative to the file using the macro. A build script
+/// using `write_asset_table` picks up every file without listing them.
+#[macro_export]
+macro_rules! embed_files {
+    ($root:expr, [$($path:expr),* $(,)*]) => {
+        $crate::vfs::EmbeddedFiles::new()
+            $(.with_file($path, include_bytes!(concat!($root, $path))))*
+    };
+}
+
+/// Writes an `EmbeddedFiles` table holding every file under `dir` to
+/// `out_file`, as Rust source for `include!`. Meant to be called from a
+/// build script, with Servo as a build dependency:
+///
+/// ```ignore
+/// // build.rs
+/// let out = Path::new(&env::var("OUT_DIR").unwrap()).join("assets.rs");
+/// servo::vfs::write_asset_table(Path::new("static"), &out).unwrap();
+///
+/// // main.rs
+/// let files = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+/// let server = Server::new().with_static_files(files);
+/// ```
+///
+/// ETags and modification times are worked out here, at build time. Hidden
+/// files and directories are left out. `cargo:rerun-if-changed` lines are
+/// printed so Cargo runs the build script again when the files change.
+pub fn write_asset_table(dir: &Path, out_file: &Path) -> io::Result<()> {
+    let mut paths = Vec::new();
+    collect_files(dir, "", &mut paths)?;
+    let mut source = String::from("::servo::vfs::EmbeddedFiles::from_table(&[\n");
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for (path, file) in paths {
+        let absolute = fs::canonicalize(&file)?;
+        let contents = fs::read(&absolute)?;
+        let modified = fs::metadata(&absolute)?.modified().ok()
+            .map(|modified| DateTime::from_system_time(modified).to_unix());
+        println!("cargo:rerun-if-changed={}", absolute.display());
+        source.push_str(&format!("    ::servo::vfs::EmbeddedFile::new({:?}, include_bytes!({:?}), {:?}, {:?}),\n",
+                                 path, absolute.display().to_string(), content_etag(&contents), modified));
+    }
+    source.push_str("])\n");
+    File::create(out_file)?.write_all(source.as_bytes())
+}
+
+/// Adds the files under `dir` to `paths`, sorted, along with the relative
+/// path each is served at.
+fn collect_files(dir: &Path, prefix: &str, paths: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    let mut entries: Vec<(String, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok().map(|name| (name, entry.path())))
+        .filter(|entry| !entry.0.starts_with('.'))
+        .collect();
+    entries.sort();
+    for (name, path) in entries {
+        let relative = format!("{}{}", prefix, name);
+        if path.is_dir() {
+            collect_files(&path, &format!("{}/", relative), paths)?;
+        } else {
+            paths.push((relative, path));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_embedded_files() {
+    use super::{Configuration, Routes, Server, get_html, route_request};
+    use static_files::StaticMount;
+    use http::Request;
+    let get = |route: &str| Request::new().with_method(String::from("GET")).with_route(String::from(route));
+    let files = EmbeddedFiles::new()
+        .with_file("index.html", b"<h1>Home</h1>")
+        .with_file("css/app.css", b"body {}")
+        .with_file("css/print.css", b"nav {}");
+    assert_eq!(files.read_dir("", &StaticOptions::default()).unwrap(), vec!["css", "index.html"]);
+    assert_eq!(files.read_dir("css", &StaticOptions::default()).unwrap(), vec!["app.css", "print.css"]);
+    assert!(files.metadata("css", &StaticOptions::default()).unwrap().is_dir());
+    assert_eq!(files.metadata("css/app", &StaticOptions::default()).unwrap_err().get_status(), 404);
+
+    let configs = Configuration::new()
+        .with_server_configurations(Server::new()
+            .with_static_files(files)
+            .with_html_files(EmbeddedFiles::new().with_file("page.html", b"<p>Hi</p>")))
+        .with_routes(Routes::new()
+            .with_static_mount(StaticMount::new("docs", "/docs").with_files(embed_files!("../", ["README.md"]))));
+    let get_css = |headers: &str| route_request(
+        Request::from(format!("GET /static/css/app.css HTTP/1.1\r\n{}\r\n", headers).as_str()), &configs);
+    let resp = get_css("").buffered().unwrap();
+    assert_eq!(resp.get_status(), 200);
+    assert_eq!(resp.get_body(), b"body {}".to_vec());
+    let etag = content_etag(b"body {}");
+    assert_eq!(resp.get_headers().unwrap()["ETag"], etag);
+    assert_eq!(get_css(&format!("If-None-Match: {}\r\n", etag)).get_status(), 304);
+    assert_eq!(get_css("Range: bytes=0-3\r\n").buffered().unwrap().get_body(), b"body".to_vec());
+    assert_eq!(route_request(get("/static/"), &configs).buffered().unwrap().get_body(), b"<h1>Home</h1>".to_vec());
+    assert_eq!(route_request(get("/static/css"), &configs).get_status(), 301);
+    assert_eq!(route_request(get("/static/../Cargo.toml"), &configs).get_status(), 400);
+    assert_eq!(route_request(get("/static/missing.js"), &configs).get_status(), 404);
+    let readme = route_request(get("/docs/README.md"), &configs).buffered().unwrap();
+    assert!(readme.get_body().starts_with(b"# "));
+    assert_eq!(get_html("page.html", &configs), "<p>Hi</p>");
+    assert_eq!(get_html("missing.html", &configs), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_files_confine_reads() {
+    use std::os::unix::fs::symlink;
+    let root = ::std::env::temp_dir().join(format!("servo_vfs_confine_{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("public")).unwrap();
+    fs::create_dir_all(root.join("outside")).unwrap();
+    fs::write(root.join("outside/secret.txt"), "secret").unwrap();
+    fs::write(root.join("public/app.css"), "body {}").unwrap();
+    symlink(root.join("outside"), root.join("public/link")).unwrap();
+    let files = DirectoryFiles::new(&root.join("public").display().to_string());
+    let confined = StaticOptions::default();
+    let mut contents = String::new();
+    files.open("app.css", 5, &confined).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "{}");
+    assert_eq!(files.open("link/secret.txt", 0, &confined).err().unwrap().kind(), io::ErrorKind::NotFound);
+    assert!(files.read_dir("link", &confined).is_err());
+    assert!(files.open("../outside/secret.txt", 0, &confined).is_err());
+    let follow = StaticOptions { follow_symlinks: true, ..StaticOptions::default() };
+    assert!(files.open("link/secret.txt", 0, &follow).is_ok());
+    assert_eq!(files.read_dir("link", &follow).unwrap(), vec!["secret.txt"]);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_write_asset_table() {
+    let root = ::std::env::temp_dir().join(format!("servo_assets_{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("static/css")).unwrap();
+    fs::write(root.join("static/css/app.css"), "body {}").unwrap();
+    fs::write(root.join("static/index.html"), "<h1>Home</h1>").unwrap();
+    fs::write(root.join("static/.env"), "SECRET=1").unwrap();
+    write_asset_table(&root.join("static"), &root.join("assets.rs")).unwrap();
+    let source = fs::read_to_string(root.join("assets.rs")).unwrap();
+    assert!(source.starts_with("::servo::vfs::EmbeddedFiles::from_table(&[\n"));
+    let css = fs::canonicalize(root.join("static/css/app.css")).unwrap();
+    assert!(source.contains(&format!("EmbeddedFile::new(\"css/app.css\", include_bytes!({:?}), {:?}, Some(",
+                                     css.display().to_string(), content_etag(b"body {}"))));
+    assert!(source.find("\"css/app.css\"").unwrap() < source.find("\"index.html\"").unwrap());
+    assert!(!source.contains(".env"));
+    fs::remove_dir_all(&root).unwrap();
+}
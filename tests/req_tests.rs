@@ -1,7 +1,8 @@
 extern crate servo;
-use servo::http::{Request, Response};
+use servo::http::{HttpError, IntoResponse, Request, Response};
 use servo::http::content_type::ContentType;
-use std::collections::HashMap;
+use servo::http::json::{self, JsonValue};
+use std::collections::{BTreeMap, HashMap};
 
 
 #[test]
@@ -87,3 +88,178 @@ fn test_empty_params_from_route() {
     let params: HashMap<String, String> = HashMap::new();
     assert_eq!(request.get_query_params() , params)
 }
+
+#[test]
+fn test_json_nesting_limit() {
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(JsonValue::parse(&nested(json::MAX_DEPTH)).is_ok());
+    assert_eq!(JsonValue::parse(&nested(json::MAX_DEPTH + 1)), Err(String::from("nesting too deep")));
+    assert_eq!(JsonValue::parse(&"[{\"a\":".repeat(200_000)), Err(String::from("nesting too deep")));
+}
+
+// Tests for turning handler output into responses
+#[test]
+fn test_json_stringify() {
+    let mut scores = BTreeMap::new();
+    scores.insert("b".to_string(), vec![1.5, 2.0]);
+    let value = JsonValue::object(vec![("name", JsonValue::from("a \"quoted\"\nline")),
+                                       ("count", JsonValue::from(3)),
+                                       ("missing", JsonValue::from(None::<i32>)),
+                                       ("scores", JsonValue::from(scores))]);
+    assert_eq!(value.stringify(),
+               "{\"count\":3,\"missing\":null,\"name\":\"a \\\"quoted\\\"\\nline\",\"scores\":{\"b\":[1.5,2]}}")
+}
+
+#[test]
+fn test_result_into_response() {
+    let ok: Result<&'static str, HttpError> = Ok("fine");
+    let err: Result<&'static str, HttpError> = Err(HttpError::new(409, "conflict"));
+    assert_eq!(ok.into_response().get_status(), 200);
+    let response = err.into_response();
+    assert_eq!(response.get_status(), 409);
+    assert_eq!(response.get_body(), b"conflict".to_vec())
+}
+
+// Tests for reading request bodies
+#[test]
+fn test_request_body_from_str() {
+    let request = Request::from("POST /submit HTTP/1.1\r\nHost: localhost:8000\r\nContent-Length: 11\r\n\r\nkey: value\n");
+    assert_eq!(request.get_body(), b"key: value\n".to_vec());
+    assert_eq!(request.get_header("host"), Some("localhost:8000".to_string()));
+    assert_eq!(request.get_headers().len(), 2)
+}
+
+#[test]
+fn test_request_body_from_bytes() {
+    let mut raw = b"PUT /upload HTTP/1.1\r\n\r\n".to_vec();
+    raw.extend_from_slice(&[0xff, 0x00, 0xfe]);
+    let request = Request::from_bytes(&raw);
+    assert_eq!(request.get_method(), "PUT");
+    assert_eq!(request.get_body(), vec![0xff, 0x00, 0xfe])
+}
+
+#[test]
+fn test_parse_params() {
+    let params = servo::http::parse_params("name=Ollie+M&city=Portland%2C%20OR&flag");
+    assert_eq!(params["name"], "Ollie M");
+    assert_eq!(params["city"], "Portland, OR");
+    assert_eq!(params["flag"], "")
+}
+
+#[test]
+fn test_request_parse() {
+    let request = Request::parse(b"GET /home?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    assert_eq!(request.get_route(), "GET /home?x=1");
+    assert_eq!(Request::parse(b"garbage\r\n\r\n").unwrap_err().get_status(), 400);
+    assert!(Request::parse(b"GET home HTTP/1.1\r\n\r\n").is_err());
+    assert!(Request::parse(b"GET / SPDY/3\r\n\r\n").is_err())
+}
+
+#[test]
+fn test_request_prefers_json() {
+    let accepting = |accept: &str| Request::new().with_header((String::from("Accept"), String::from(accept)));
+    assert!(!Request::new().prefers_json());
+    assert!(accepting("application/json").prefers_json());
+    assert!(accepting("application/problem+json, */*;q=0.1").prefers_json());
+    assert!(!accepting("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8").prefers_json());
+    assert!(!accepting("text/html, application/json").prefers_json())
+}
+
+#[test]
+fn test_request_encoding_quality() {
+    let accepting = |accept: &str| Request::new().with_header((String::from("Accept-Encoding"), String::from(accept)));
+    assert_eq!(Request::new().encoding_quality("gzip"), 0.0);
+    assert_eq!(Request::new().encoding_quality("identity"), 1.0);
+    assert_eq!(accepting("gzip, deflate, br").encoding_quality("br"), 1.0);
+    assert_eq!(accepting("GZIP;q=0.5, br;q=0").encoding_quality("gzip"), 0.5);
+    assert_eq!(accepting("gzip;q=0.5, br;q=0").encoding_quality("br"), 0.0);
+    assert_eq!(accepting("br, *;q=0.2").encoding_quality("gzip"), 0.2);
+    assert_eq!(accepting("*;q=0").encoding_quality("identity"), 0.0);
+}
+
+#[test]
+fn test_date_conversions() {
+    use servo::http::date::DateTime;
+    let date = DateTime::from_unix(971_186_136);
+    assert_eq!(date.to_clf(), "10/Oct/2000:13:55:36 +0000");
+    assert_eq!(date.to_iso8601(), "2000-10-10T13:55:36Z");
+    assert_eq!(date.to_unix(), 971_186_136);
+    assert_eq!(DateTime::from_unix(0).to_iso8601(), "1970-01-01T00:00:00Z");
+    assert_eq!(DateTime::from_unix(951_825_600).to_iso8601(), "2000-02-29T12:00:00Z");
+    assert_eq!(DateTime::from_unix(-1).to_iso8601(), "1969-12-31T23:59:59Z");
+    for seconds in (0..4_000_000_000i64).step_by(86_399 * 37) {
+        assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+    }
+}
+
+#[test]
+fn test_http_dates() {
+    use servo::http::date::DateTime;
+    let date = DateTime::from_unix(784_111_777);
+    assert_eq!(date.to_http_date(), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(DateTime::from_unix(971_186_136).to_http_date(), "Tue, 10 Oct 2000 13:55:36 GMT");
+    assert_eq!(DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+    assert_eq!(DateTime::parse_http_date("Sun Nov  6 08:49:37 1994"), Some(date));
+    assert_eq!(DateTime::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(date));
+    assert_eq!(DateTime::parse_http_date("Tuesday, 10-Oct-00 13:55:36 GMT").map(|d| d.to_unix()), Some(971_186_136));
+    assert_eq!(DateTime::parse_http_date("Tue, 10 Oct 2000 13:55:36 UTC"), None);
+    assert_eq!(DateTime::parse_http_date("Tue, 31 Feb 2000 13:55:36 GMT"), None);
+    assert_eq!(DateTime::parse_http_date("Tue, 10 Oct 2000 24:00:00 GMT"), None);
+    assert_eq!(DateTime::parse_http_date("Tue, 10 Foo 2000 13:55:36 GMT"), None);
+    assert_eq!(DateTime::parse_http_date("yesterday"), None);
+    for seconds in (0..4_000_000_000i64).step_by(86_399 * 37) {
+        let date = DateTime::from_unix(seconds);
+        assert_eq!(DateTime::parse_http_date(&date.to_http_date()), Some(date));
+    }
+}
+
+#[test]
+fn test_request_ids() {
+    let generated = servo::http::generate_request_id();
+    assert_eq!(generated.len(), 36);
+    assert_eq!(&generated[14..15], "4");
+    assert_ne!(generated, servo::http::generate_request_id());
+    let request = Request::from("GET / HTTP/1.1\r\nX-Request-Id: upstream-42\r\n\r\n").ensure_request_id();
+    assert_eq!(request.get_request_id(), "upstream-42");
+    assert_eq!(request.clone().ensure_request_id(), request);
+    let request = Request::from("GET / HTTP/1.1\r\nX-Request-Id: has spaces\r\n\r\n").ensure_request_id();
+    assert_eq!(request.get_request_id().len(), 36);
+    assert_eq!(Request::new().get_request_id(), "")
+}
+
+#[test]
+fn test_streaming_responses() {
+    use std::io::Cursor;
+    let chunked = Response::new().with_status(200).with_stream(Cursor::new(b"hello world".to_vec()), None);
+    assert!(chunked.is_streaming());
+    assert!(chunked.get_body().is_empty());
+    let bytes = String::from_utf8(chunked.byteify()).unwrap();
+    assert!(bytes.contains("Transfer-Encoding: chunked"));
+    assert!(!bytes.contains("Content-Length"));
+    assert!(bytes.ends_with("\r\nb\r\nhello world\r\n0\r\n\r\n"));
+
+    let sized = Response::new().with_status(200).with_stream(Cursor::new(b"hello world".to_vec()), Some(5));
+    let mut out = Vec::new();
+    assert_eq!(sized.write_to(&mut out).unwrap(), 5);
+    let bytes = String::from_utf8(out).unwrap();
+    assert!(bytes.contains("Content-Length: 5"));
+    assert!(bytes.ends_with("\r\nhello"));
+
+    let short = Response::new().with_status(200).with_stream(Cursor::new(b"hi".to_vec()), Some(5));
+    assert!(short.write_to(&mut Vec::new()).is_err());
+    let buffered = Response::new().with_status(200).with_stream(Cursor::new(b"hello".to_vec()), None).buffered().unwrap();
+    assert!(!buffered.is_streaming());
+    assert_eq!(buffered.get_body(), b"hello".to_vec());
+    assert_eq!(buffered, Response::new().with_status(200).with_body(b"hello".to_vec()));
+}
+
+#[test]
+fn test_request_accepts_html() {
+    let with_accept = |accept: &str| Request::from(format!("GET / HTTP/1.1\r\nAccept: {}\r\n\r\n", accept).as_str());
+    assert!(with_accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8").accepts_html());
+    assert!(with_accept("application/xhtml+xml").accepts_html());
+    assert!(!with_accept("*/*").accepts_html());
+    assert!(!with_accept("application/json").accepts_html());
+    assert!(!with_accept("text/html;q=0, application/json").accepts_html());
+    assert!(!Request::from("GET / HTTP/1.1\r\n\r\n").accepts_html());
+}